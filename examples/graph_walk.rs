@@ -2,23 +2,274 @@ use anyhow::{Context, Ok};
 use beanstalkc::Beanstalkc;
 use itertools::Itertools;
 use random_string::generate;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use silkworm::{run_worker, DataCycle, Registry};
-use std::collections::hash_map::DefaultHasher;
+use silkworm::{
+    content_id, export_all, export_dot, merge_shards, rows, run_worker, variant, verify_store,
+    AmqpQueue, BaseSnapshot, Budget, Checkpoint, CollapsePolicies, DataCycle, DatabaseStore,
+    Export, ExportFormat, FileSeenSet, FrontierKind, FsStore, GraphExport, Header, LocalFrontier,
+    MemorySeenSet, MergeEvent, MergeMetrics, Mergeable, Migrations, Overlay, Policy, ProvenanceLog,
+    Registry, RegistryCheckpoint, RegistryCycle, ResultReader, RouteLookup, Rows, S3Store,
+    SeenMode, SeenSet, Shard, ShardPolicies, SqliteColumns, SqliteStore, StoreReport, Typed,
+    TypedCycle, Variant,
+};
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::hash::{Hash, Hasher};
-use std::io::Read;
-use std::io::Write;
+use std::convert::Infallible;
+use std::fs;
+use std::hash::Hash;
 use std::iter::once;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{process, vec};
 
+// bumped whenever a persisted type changes shape, with a step in `migrations` for each
+// file kind or table that changed
+const SCHEMA_VERSION: u32 = 2;
+
+fn migrations() -> Migrations {
+    // v2 added the `shortest` table. Stores read a missing table as empty, but checkpoints hold
+    // a whole `GraphData`
+    Migrations::new().step(1, "checkpoint", |old: CheckpointV1| Checkpoint {
+        db: GraphData {
+            nodes: old.db.nodes,
+            edges: old.db.edges,
+            input_files: old.db.input_files,
+            paths: old.db.paths,
+            shortest: HashMap::new(),
+        },
+        local_queue: old.local_queue,
+        queue_to_write: old.queue_to_write,
+        provenance: old.provenance,
+    })
+}
+
 fn main() {
     println!("Hello from an example!");
-    let holder = Holder {};
+    // `graph_walk paths <from> <to>`, `graph_walk export <jsonl|csv> <dir>` and
+    // `graph_walk dot <from> <to>` read the finished search instead of working on it, and
+    // `graph_walk verify` checks the stored files for damage
+    let args: Vec<String> = std::env::args().collect();
+    let query = args
+        .get(1)
+        .filter(|arg| ["paths", "export", "dot", "verify"].contains(&arg.as_str()));
+    let frontier = args
+        .get(1)
+        .filter(|_| query.is_none())
+        .map(|arg| arg.parse::<FrontierKind>())
+        .transpose()
+        .unwrap()
+        .unwrap_or_default();
+    let budget = Budget {
+        max_routes: std::env::var("SILKWORM_MAX_ROUTES")
+            .ok()
+            .map(|max| max.parse().unwrap()),
+        ..Budget::default()
+    };
+    let steal_interval = std::env::var("SILKWORM_STEAL_INTERVAL")
+        .ok()
+        .map(|interval| interval.parse().unwrap());
+    let checkpoint_interval = std::env::var("SILKWORM_CHECKPOINT_INTERVAL")
+        .ok()
+        .map(|interval| interval.parse().unwrap());
+    let seen: Box<dyn SeenSet> = match std::env::var("SILKWORM_SEEN_FILE") {
+        std::result::Result::Ok(path) => {
+            Box::new(FileSeenSet::open(path, SeenMode::Exact).unwrap())
+        }
+        Err(_) => Box::new(MemorySeenSet::new(SeenMode::Exact)),
+    };
+    let codec = std::env::var("SILKWORM_CODEC")
+        .ok()
+        .map(|codec| codec.parse().unwrap())
+        .unwrap_or_default();
+    let compression = std::env::var("SILKWORM_COMPRESSION")
+        .ok()
+        .map(|compression| compression.parse().unwrap())
+        .unwrap_or_default();
+    let backend = std::env::var("SILKWORM_DATABASE_STORE")
+        .ok()
+        .map(|backend| backend.parse().unwrap())
+        .unwrap_or_default();
+    let store = FsStore::new(std::env::var("SILKWORM_STORE_DIR").unwrap_or(".".to_string()))
+        .unwrap()
+        .with_header(Header::new(codec, SCHEMA_VERSION).with_compression(compression))
+        .with_migrations(migrations())
+        .with_columns(sqlite_columns());
+    // workers on hosts that don't share a filesystem share a bucket instead, each caching it
+    // under the same SILKWORM_STORE_DIR, as queued locations are paths under it, and holding
+    // at most SILKWORM_S3_CACHE_BYTES there
+    let store = match std::env::var("SILKWORM_S3_ENDPOINT") {
+        std::result::Result::Ok(endpoint) => {
+            let var = |name| std::env::var(name).unwrap_or_default();
+            let region = std::env::var("AWS_REGION").unwrap_or("us-east-1".to_string());
+            let store = match var("SILKWORM_S3_CACHE_BYTES").parse() {
+                std::result::Result::Ok(bytes) => store.with_cache_limit(bytes),
+                Err(_) => store,
+            };
+            store.with_s3(
+                S3Store::new(&endpoint, &var("SILKWORM_S3_BUCKET"))
+                    .unwrap()
+                    .with_credentials(&var("AWS_ACCESS_KEY_ID"), &var("AWS_SECRET_ACCESS_KEY"))
+                    .with_region(&region),
+            )
+        }
+        Err(_) => store,
+    };
+    let holder = Holder {
+        frontier,
+        budget,
+        steal_interval,
+        checkpoint_interval,
+        seen: RefCell::new(seen),
+        store: Rc::new(store),
+        backend,
+        amqp_url: std::env::var("SILKWORM_AMQP_URL").ok(),
+    };
+
+    if query.is_some_and(|query| query == "verify") {
+        if !verify(&holder).unwrap() {
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(query) = query {
+        let results = ResultReader::open(holder).unwrap();
+        match query.as_str() {
+            "paths" => {
+                let node = |label: &String| Node {
+                    label: label.clone(),
+                };
+                let ends = ends_id(&node(&args[2]), &node(&args[3]));
+                if let Some(path) = results.db().get(|t| &t.shortest, &ends) {
+                    println!("shortest: {}", path_labels(path).join(" -> "));
+                }
+                for path in paths_between(&results, &args[2], &args[3]) {
+                    println!("{}", path_labels(&path).join(" -> "));
+                }
+            }
+            "export" => {
+                let format = args[2].parse::<ExportFormat>().unwrap();
+                export_all(results.db(), format, Path::new(&args[3])).unwrap();
+            }
+            _ => {
+                let highlight = paths_between(&results, &args[2], &args[3])
+                    .iter()
+                    .map(|path| path_labels(path).into_iter().map(str::to_owned).collect())
+                    .collect_vec();
+                export_dot(results.db(), &highlight, std::io::stdout()).unwrap();
+            }
+        }
+        return;
+    }
+
+    let store = holder.store.clone();
+    if let Some(metrics) = run_worker(holder).unwrap() {
+        for (table, table_metrics) in &metrics.tables {
+            println!(
+                "merged {} {table}, {} shared, {} conflicting",
+                table_metrics.merged, table_metrics.shared, table_metrics.conflicts
+            );
+        }
+        let storage = store.storage_metrics();
+        println!(
+            "stored {} bytes for {} encoded, a compression ratio of {:.2}",
+            storage.stored,
+            storage.raw,
+            storage.ratio()
+        );
+    }
+}
+
+// reports damaged files in the store and the pending merges that would read them, returning
+// whether everything was intact
+fn verify(holder: &Holder) -> Result<bool, anyhow::Error> {
+    let report = verify_store(holder.store.root())?;
+    for (path, e) in &report.damaged {
+        println!("damaged {}: {e:#}", path.display());
+    }
+    for path in &report.unchecked {
+        println!("not checked {}", path.display());
+    }
+    println!(
+        "{} of {} files damaged",
+        report.damaged.len(),
+        report.checked.len()
+    );
+    if report.damaged.is_empty() {
+        return Ok(true);
+    }
+
+    // every announced merge job, not just the next, may be the one that reads a damaged file
+    let pending = pending_merges(holder, &report)?;
+    println!("{} merge jobs pending", pending.len());
+    for (db, db_queue) in pending {
+        if report.is_damaged(&db) || report.is_damaged(&db_queue) {
+            println!(
+                "a pending merge references {} and {}",
+                db.display(),
+                db_queue.display()
+            );
+        }
+    }
+    Ok(false)
+}
+
+// the shard and queue a merge job announces
+type MergeJob = (PathBuf, PathBuf);
+
+// every merge job announced and not yet merged, read from the records `produce_merge_event`
+// keeps in the store rather than from the queue, which can only show the next job without
+// reserving it. Records that are themselves damaged are left to the report
+fn pending_merges(holder: &Holder, report: &StoreReport) -> Result<Vec<MergeJob>, anyhow::Error> {
+    let dir = holder.store.path("merges");
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut pending = vec![];
+    for entry in fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))? {
+        let record = entry?.path();
+        let name = record.file_name().unwrap_or_default().to_string_lossy();
+        // temporary files of records being written
+        if name.starts_with('.') || report.is_damaged(&record) {
+            continue;
+        }
+        pending.push(holder.store.read("merge", &record)?);
+    }
+    pending.sort();
+    Ok(pending)
+}
+
+// where the shard and queue a merge job announces are recorded until the merge deletes the
+// shard, in the `merges` directory `pending_merges` lists
+fn merge_record(store: &FsStore, db: &Path) -> Result<PathBuf, anyhow::Error> {
+    let name = db.file_name().context("store locations name a file")?;
+    Ok(store.path("merges").join(name))
+}
 
-    run_worker(holder).unwrap();
+// every path found from the node labelled `from` to the node labelled `to`
+fn paths_between(results: &ResultReader<Holder>, from: &str, to: &str) -> Vec<GraphPath> {
+    results
+        .all::<GraphPath>()
+        .filter(|path| {
+            path.edges
+                .first()
+                .is_some_and(|edge| edge.from.label == from)
+                && path.edges.last().is_some_and(|edge| edge.to.label == to)
+        })
+        .sorted_by_key(|path| path.edges.len())
+        .collect()
+}
+
+fn path_labels(path: &GraphPath) -> Vec<&str> {
+    path.edges
+        .first()
+        .map(|edge| edge.from.label.as_str())
+        .into_iter()
+        .chain(path.edges.iter().map(|edge| edge.to.label.as_str()))
+        .collect()
 }
 
 #[derive(PartialEq, Eq, Hash, Serialize, Deserialize, Default, Clone)]
@@ -42,15 +293,29 @@ struct Edge {
     to: Node,
 }
 
-#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Mergeable)]
 struct GraphData {
     nodes: HashMap<u64, Node>,
     edges: HashMap<u64, Edge>,
     input_files: HashMap<u64, InputFile>,
     paths: HashMap<u64, GraphPath>,
+    // the shortest path found between two nodes, keyed by the pair, so unlike the other tables
+    // two shards can hold different values under one key
+    shortest: HashMap<u64, GraphPath>,
+}
+
+// `GraphData` as schema version 1 persisted it
+#[derive(Serialize, Deserialize)]
+struct GraphDataV1 {
+    nodes: HashMap<u64, Node>,
+    edges: HashMap<u64, Edge>,
+    input_files: HashMap<u64, InputFile>,
+    paths: HashMap<u64, GraphPath>,
 }
 
-#[derive(PartialEq, Serialize, Deserialize)]
+type CheckpointV1 = Checkpoint<GraphDataV1, LocalFrontier<DatabaseLocation>>;
+
+#[derive(Clone, PartialEq, Hash, Serialize, Deserialize)]
 enum Data {
     Node(Node),
     Edge(Edge),
@@ -58,144 +323,415 @@ enum Data {
     GraphPath(GraphPath),
 }
 
+variant!(
+    Data::Node(Node),
+    Data::Edge(Edge),
+    Data::InputFile(InputFile),
+    Data::GraphPath(GraphPath),
+);
+
+// the friends of every graph cycle
+#[derive(Clone)]
+enum PathPiece {
+    Edge(Edge),
+    GraphPath(GraphPath),
+}
+
+impl Variant<Data> for PathPiece {
+    fn into_data(self) -> Data {
+        match self {
+            PathPiece::Edge(edge) => Data::Edge(edge),
+            PathPiece::GraphPath(path) => Data::GraphPath(path),
+        }
+    }
+
+    fn from_data(data: &Data) -> Option<Self> {
+        match data {
+            Data::Edge(edge) => Some(PathPiece::Edge(edge.clone())),
+            Data::GraphPath(path) => Some(PathPiece::GraphPath(path.clone())),
+            _ => None,
+        }
+    }
+
+    fn names() -> Vec<&'static str> {
+        vec!["Edge", "GraphPath"]
+    }
+}
+
+// what an input file is parsed into
+#[derive(Clone)]
+enum Element {
+    Node(Node),
+    Edge(Edge),
+}
+
+impl Variant<Data> for Element {
+    fn into_data(self) -> Data {
+        match self {
+            Element::Node(node) => Data::Node(node),
+            Element::Edge(edge) => Data::Edge(edge),
+        }
+    }
+
+    fn from_data(data: &Data) -> Option<Self> {
+        match data {
+            Data::Node(node) => Some(Element::Node(node.clone())),
+            Data::Edge(edge) => Some(Element::Edge(edge.clone())),
+            _ => None,
+        }
+    }
+
+    fn names() -> Vec<&'static str> {
+        vec!["Node", "Edge"]
+    }
+}
+
 #[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
 struct DatabaseLocation {
     data_type: String,
     hash: u64,
 }
 
-impl DataCycle for Node {
-    type Database = GraphData;
-    type DataRoute = DatabaseLocation;
-    type Data = Data;
+impl Export for GraphData {
+    fn tables(&self) -> Vec<&'static str> {
+        vec!["nodes", "edges", "input_files", "paths", "shortest"]
+    }
 
-    fn stop_categorically(&self, db: &Self::Database) -> bool {
-        db.edges.is_empty()
+    fn rows(&self, table: &str) -> Rows<'_> {
+        match table {
+            "nodes" => rows(self.nodes.iter()),
+            "edges" => rows(self.edges.iter()),
+            "input_files" => rows(self.input_files.iter()),
+            "paths" => rows(self.paths.iter()),
+            _ => rows(self.shortest.iter()),
+        }
     }
+}
 
-    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Data> {
-        db.nodes
-            .get(&route.hash)
-            .map(|node| Data::Node(node.to_owned()))
+impl GraphExport for GraphData {
+    fn graph_nodes(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        Box::new(self.nodes.values().map(|node| node.label.clone()))
     }
 
-    fn get_friends(&self, db: &Self::Database, route: &Self::DataRoute) -> Vec<Self::Data> {
-        // paths are inhabited and so do not need to be checked
-        // edges may not be inhabited, so the non-missing node must be checked to be an edge eligible for path inclusion
+    fn graph_edges(&self) -> Box<dyn Iterator<Item = (String, String)> + '_> {
+        Box::new(
+            self.edges
+                .values()
+                .map(|edge| (edge.from.label.clone(), edge.to.label.clone())),
+        )
+    }
+}
 
-        // beginning
-        // missing node on the left - ox : xxxxxxx
-        // missing node on the right - xo : xxxxxx
-        // end
-        // missing node on the left - xxxxx : ox
-        // missing node on the right - xxxxx : xo
-        let data = self
-            .get_data(db, route)
-            .expect("Do not get friends of nonexistent data");
+// tables keyed by content id hold identical values under shared keys, so only the shortest
+// paths between two nodes can conflict
+fn collapse_policies() -> CollapsePolicies<GraphData> {
+    CollapsePolicies::<GraphData>::new()
+        .table("nodes", |db| &mut db.nodes, Policy::last_writer_wins())
+        .table("edges", |db| &mut db.edges, Policy::last_writer_wins())
+        .table(
+            "input_files",
+            |db| &mut db.input_files,
+            Policy::last_writer_wins(),
+        )
+        .table("paths", |db| &mut db.paths, Policy::last_writer_wins())
+        .table("shortest", |db| &mut db.shortest, shortest_path())
+}
 
-        let missing_node;
-        if let Data::Node(node) = data {
-            missing_node = node;
-        } else {
-            panic!("route in data cycle must point to a node")
+// the same policies for merges that stream through stored shards
+fn shard_policies() -> ShardPolicies {
+    ShardPolicies::new()
+        .table("nodes", Policy::<Node>::last_writer_wins())
+        .table("edges", Policy::<Edge>::last_writer_wins())
+        .table("input_files", Policy::<InputFile>::last_writer_wins())
+        .table("paths", Policy::<GraphPath>::last_writer_wins())
+        .table("shortest", shortest_path())
+}
+
+// what analysts query the SQLite result database by
+fn sqlite_columns() -> SqliteColumns {
+    SqliteColumns::new()
+        .column("nodes", "label", |node: &Node| node.label.clone())
+        .column("edges", "source", |edge: &Edge| edge.from.label.clone())
+        .column("edges", "target", |edge: &Edge| edge.to.label.clone())
+        .column("paths", "source", |path: &GraphPath| {
+            path.edges.first().map(|edge| edge.from.label.clone())
+        })
+        .column("paths", "target", |path: &GraphPath| {
+            path.edges.last().map(|edge| edge.to.label.clone())
+        })
+        .column("paths", "length", |path: &GraphPath| {
+            path.edges.len() as i64
+        })
+}
+
+fn shortest_path() -> Policy<GraphPath> {
+    Policy::min_by_key(|path: &GraphPath| path.edges.len())
+}
+
+// job databases read through to the published base
+type GraphDb = Overlay<GraphData>;
+
+fn insert<T: Hash + Clone>(
+    db: &mut GraphDb,
+    table: fn(&GraphData) -> &HashMap<u64, T>,
+    table_mut: fn(&mut GraphData) -> &mut HashMap<u64, T>,
+    data_type: &str,
+    item: &T,
+) -> Option<DatabaseLocation> {
+    let hash = content_id(item);
+    let inserted = db.insert(table, table_mut, hash, item.to_owned());
+
+    inserted.then(|| DatabaseLocation {
+        data_type: data_type.to_string(),
+        hash,
+    })
+}
+
+fn save_paths(db: &mut GraphDb, new_data: Vec<&GraphPath>) -> Vec<Option<DatabaseLocation>> {
+    new_data
+        .into_iter()
+        .map(|path| {
+            save_shortest(db, path);
+            insert(db, |t| &t.paths, |t| &mut t.paths, "paths", path)
+        })
+        .collect_vec()
+}
+
+fn ends_id(from: &Node, to: &Node) -> u64 {
+    content_id(&(from, to))
+}
+
+// keeps a path as the shortest between its ends unless one at most as long is known
+fn save_shortest(db: &mut GraphDb, path: &GraphPath) {
+    let (Some(first), Some(last)) = (path.edges.first(), path.edges.last()) else {
+        return;
+    };
+    let ends = ends_id(&first.from, &last.to);
+    let known = db.get(|t| &t.shortest, &ends);
+    if known.is_none_or(|known| path.edges.len() < known.edges.len()) {
+        db.delta_mut().shortest.insert(ends, path.clone());
+    }
+}
+
+// the tables the graph cycles find friends in, whether in memory or stored
+trait GraphTables {
+    fn has_node(&self, node: &Node) -> Result<bool, anyhow::Error>;
+    fn each_edge(&self, visit: &mut dyn FnMut(&Edge)) -> Result<(), anyhow::Error>;
+    fn each_path(&self, visit: &mut dyn FnMut(&GraphPath)) -> Result<(), anyhow::Error>;
+
+    fn edges(&self, keep: impl Fn(&Edge) -> bool) -> Result<Vec<Edge>, anyhow::Error>
+    where
+        Self: Sized,
+    {
+        let mut edges = vec![];
+        self.each_edge(&mut |edge| {
+            if keep(edge) {
+                edges.push(edge.clone());
+            }
+        })?;
+        Ok(edges)
+    }
+
+    // edges whose other end is a node in the database
+    fn inhabited_edges(
+        &self,
+        keep: impl Fn(&Edge) -> bool,
+        other_end: fn(&Edge) -> &Node,
+    ) -> Result<Vec<Edge>, anyhow::Error>
+    where
+        Self: Sized,
+    {
+        let mut inhabited = vec![];
+        for edge in self.edges(keep)? {
+            if self.has_node(other_end(&edge))? {
+                inhabited.push(edge);
+            }
         }
+        Ok(inhabited)
+    }
+}
 
-        let missing_node_on_left_edges = db
-            .edges
-            .iter()
-            .filter(|(_, inner_edge)| {
-                missing_node == inner_edge.from && db.nodes.values().contains(&inner_edge.to)
-            })
-            .map(|(_, inner_edge)| inner_edge)
-            .cloned();
-        let missing_node_on_right_edges = db
-            .edges
-            .iter()
-            .filter(|(_, inner_edge)| {
-                missing_node == inner_edge.to && db.nodes.values().contains(&inner_edge.from)
-            })
-            .map(|(_, inner_edge)| inner_edge)
-            .cloned();
+impl GraphTables for GraphDb {
+    // nodes are keyed by content id
+    fn has_node(&self, node: &Node) -> Result<bool, anyhow::Error> {
+        Ok(self.get(|t| &t.nodes, &content_id(node)).is_some())
+    }
 
-        let packaged_beginning_missing_node_on_left_paths = db
-            .paths
-            .iter()
-            .filter(|(_, graph_path)| {
-                missing_node_on_left_edges.clone().any(|inner_edge| {
-                    inner_edge.to
-                        == graph_path
-                            .edges
-                            .first()
-                            .expect("dont have empty paths")
-                            .from
-                })
-            })
-            .map(|(_, inner_graph_path)| inner_graph_path)
-            .cloned()
-            .map(|inner_graph_path| Data::GraphPath(inner_graph_path));
+    fn each_edge(&self, visit: &mut dyn FnMut(&Edge)) -> Result<(), anyhow::Error> {
+        self.values(|t| &t.edges).for_each(visit);
+        Ok(())
+    }
 
-        let packaged_beginning_missing_node_on_right_paths = db
-            .paths
-            .iter()
-            .filter(|(_, graph_path)| {
-                missing_node
-                    == graph_path
-                        .edges
-                        .first()
-                        .expect("dont have empty paths")
-                        .from
-            })
-            .map(|(_, inner_graph_path)| inner_graph_path)
-            .cloned()
-            .map(|inner_graph_path| Data::GraphPath(inner_graph_path));
+    fn each_path(&self, visit: &mut dyn FnMut(&GraphPath)) -> Result<(), anyhow::Error> {
+        self.values(|t| &t.paths).for_each(visit);
+        Ok(())
+    }
+}
 
-        let packaged_ending_missing_node_on_left_paths = db
-            .paths
-            .iter()
-            .filter(|(_, graph_path)| {
-                missing_node == graph_path.edges.last().expect("dont have empty paths").to
-            })
-            .map(|(_, inner_graph_path)| inner_graph_path)
-            .cloned()
-            .map(|inner_graph_path| Data::GraphPath(inner_graph_path));
+fn first_node(path: &GraphPath) -> &Node {
+    &path.edges.first().expect("dont have empty paths").from
+}
 
-        let packaged_ending_missing_node_on_right_paths = db
-            .paths
-            .iter()
-            .filter(|(_, graph_path)| {
-                missing_node_on_right_edges.clone().any(|inner_edge| {
-                    inner_edge.from == graph_path.edges.last().expect("dont have empty paths").to
-                })
-            })
-            .map(|(_, inner_graph_path)| inner_graph_path)
-            .cloned()
-            .map(|inner_graph_path| Data::GraphPath(inner_graph_path));
-
-        let packaged_missing_node_on_left_edges = missing_node_on_left_edges
-            .clone()
-            .map(|edge| Data::Edge(edge));
-        let packaged_missing_node_on_right_edges = missing_node_on_right_edges
-            .clone()
-            .map(|edge| Data::Edge(edge));
-
-        packaged_missing_node_on_left_edges
-            .chain(packaged_missing_node_on_right_edges)
-            .chain(packaged_beginning_missing_node_on_left_paths)
-            .chain(packaged_beginning_missing_node_on_right_paths)
-            .chain(packaged_ending_missing_node_on_left_paths)
-            .chain(packaged_ending_missing_node_on_right_paths)
-            .collect_vec()
+fn last_node(path: &GraphPath) -> &Node {
+    &path.edges.last().expect("dont have empty paths").to
+}
+
+fn node_friends(
+    db: &impl GraphTables,
+    missing_node: &Node,
+) -> Result<Vec<PathPiece>, anyhow::Error> {
+    // paths are inhabited and so do not need to be checked
+    // edges may not be inhabited, so the non-missing node must be checked to be an edge eligible for path inclusion
+
+    // beginning
+    // missing node on the left - ox : xxxxxxx
+    // missing node on the right - xo : xxxxxx
+    // end
+    // missing node on the left - xxxxx : ox
+    // missing node on the right - xxxxx : xo
+    let left_edges = db.inhabited_edges(|edge| &edge.from == missing_node, |edge| &edge.to)?;
+    let right_edges = db.inhabited_edges(|edge| &edge.to == missing_node, |edge| &edge.from)?;
+
+    let (mut beginning_left, mut beginning_right, mut ending_left, mut ending_right) =
+        (vec![], vec![], vec![], vec![]);
+    db.each_path(&mut |path| {
+        if left_edges.iter().any(|edge| &edge.to == first_node(path)) {
+            beginning_left.push(path.clone());
+        }
+        if missing_node == first_node(path) {
+            beginning_right.push(path.clone());
+        }
+        if missing_node == last_node(path) {
+            ending_left.push(path.clone());
+        }
+        if right_edges.iter().any(|edge| &edge.from == last_node(path)) {
+            ending_right.push(path.clone());
+        }
+    })?;
+
+    Ok(left_edges
+        .into_iter()
+        .chain(right_edges)
+        .map(PathPiece::Edge)
+        .chain(
+            beginning_left
+                .into_iter()
+                .chain(beginning_right)
+                .chain(ending_left)
+                .chain(ending_right)
+                .map(PathPiece::GraphPath),
+        )
+        .collect_vec())
+}
+
+fn edge_friends(
+    db: &impl GraphTables,
+    missing_edge: &Edge,
+) -> Result<Vec<PathPiece>, anyhow::Error> {
+    // All edges must be inhabited to be friends
+    // edge : edge
+    // to left
+    // to right
+
+    // edge : path
+    // path : edge
+    let left_node = &missing_edge.from;
+    let right_node = &missing_edge.to;
+
+    let edges_to_left = db.inhabited_edges(|edge| &edge.to == left_node, |edge| &edge.from)?;
+    let edges_to_right = db.inhabited_edges(|edge| &edge.from == right_node, |edge| &edge.to)?;
+
+    let (mut paths_to_right, mut paths_to_left) = (vec![], vec![]);
+    db.each_path(&mut |path| {
+        if first_node(path) == right_node {
+            paths_to_right.push(path.clone());
+        }
+        if last_node(path) == left_node {
+            paths_to_left.push(path.clone());
+        }
+    })?;
+
+    Ok(edges_to_left
+        .into_iter()
+        .chain(edges_to_right)
+        .map(PathPiece::Edge)
+        .chain(
+            paths_to_right
+                .into_iter()
+                .chain(paths_to_left)
+                .map(PathPiece::GraphPath),
+        )
+        .collect_vec())
+}
+
+fn path_friends(
+    db: &impl GraphTables,
+    missing_graph: &GraphPath,
+) -> Result<Vec<PathPiece>, anyhow::Error> {
+    // this path : other path
+    // other path : this path
+    // edge : this path
+    // this path : edge
+    let start_node = first_node(missing_graph);
+    let end_node = last_node(missing_graph);
+
+    let (mut this_then_friend, mut friend_then_this) = (vec![], vec![]);
+    db.each_path(&mut |friend| {
+        if last_node(friend) == start_node {
+            this_then_friend.push(friend.clone());
+        }
+        if first_node(friend) == end_node {
+            friend_then_this.push(friend.clone());
+        }
+    })?;
+
+    let edge_then_path =
+        db.inhabited_edges(|friend| &friend.to == start_node, |friend| &friend.from)?;
+    let path_then_edge =
+        db.inhabited_edges(|friend| &friend.to == start_node, |friend| &friend.to)?;
+
+    Ok(this_then_friend
+        .into_iter()
+        .chain(friend_then_this)
+        .map(PathPiece::GraphPath)
+        .chain(
+            edge_then_path
+                .into_iter()
+                .chain(path_then_edge)
+                .map(PathPiece::Edge),
+        )
+        .collect_vec())
+}
+
+impl TypedCycle for Node {
+    type Database = GraphDb;
+    type DataRoute = DatabaseLocation;
+    type Data = Data;
+    type Input = Node;
+    type Friend = PathPiece;
+    type Output = GraphPath;
+
+    fn stop_categorically(&self, db: &Self::Database) -> bool {
+        db.is_empty(|t| &t.edges)
+    }
+
+    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Node> {
+        db.get(|t| &t.nodes, &route.hash).cloned()
     }
 
-    fn stop_data(&self, _data: &Self::Data, _db: &Self::Database) -> bool {
+    fn get_friends(&self, db: &Self::Database, missing_node: &Node) -> Vec<PathPiece> {
+        node_friends(db, missing_node).expect("in-memory tables can't fail")
+    }
+
+    fn stop_data(&self, _data: &Node, _db: &Self::Database) -> bool {
         false
     }
 
-    fn stop_friends(&self, friends: &Vec<Self::Data>) -> bool {
+    fn stop_friends(&self, friends: &[PathPiece]) -> bool {
         friends.is_empty()
     }
 
-    fn search(&self, data: &Self::Data, friends: &Vec<Self::Data>) -> Vec<Self::Data> {
+    fn search(&self, missing_node: &Node, friends: &[PathPiece]) -> Vec<GraphPath> {
         // look for paths connected to this node. Collect into new paths. Make sure the new paths only use occupied nodes
 
         // two edges
@@ -207,38 +743,27 @@ impl DataCycle for Node {
         // missing node on the left - xxxxx : ox
         // missing node on the right - xxxxx : xo
 
-        let missing_node;
-        if let Data::Node(node) = data {
-            missing_node = node;
-        } else {
-            panic!("route in data cycle must point to a node")
-        }
-
         let mut paths: Vec<&GraphPath> = vec![];
         let mut left_edges: Vec<&Edge> = vec![];
         let mut right_edges: Vec<&Edge> = vec![];
         for friend in friends {
             match friend {
-                Data::Node(_) => panic!("nodes are not friends with nodes"),
-                Data::Edge(edge) => {
+                PathPiece::Edge(edge) => {
                     if &edge.from == missing_node {
                         left_edges.push(edge);
                     } else {
                         right_edges.push(edge);
                     }
                 }
-                Data::InputFile(_) => panic!("nodes are not friends with input files"),
-                Data::GraphPath(path) => paths.push(path),
+                PathPiece::GraphPath(path) => paths.push(path),
             }
         }
 
         let new_minimal_path = right_edges
             .iter()
             .cartesian_product(left_edges.clone())
-            .map(|(re, le)| {
-                Data::GraphPath(GraphPath {
-                    edges: vec![re.to_owned().to_owned(), le.to_owned()],
-                })
+            .map(|(re, le)| GraphPath {
+                edges: vec![re.to_owned().to_owned(), le.to_owned()],
             });
 
         let beginning = left_edges
@@ -248,13 +773,11 @@ impl DataCycle for Node {
             .filter(|(inner_edge, inner_path)| {
                 inner_edge.to == inner_path.edges.first().expect("nonempty path").from
             })
-            .map(|(inner_edge, inner_path)| {
-                Data::GraphPath(GraphPath {
-                    edges: once(inner_edge.to_owned())
-                        .chain(inner_path.edges.iter())
-                        .cloned()
-                        .collect_vec(),
-                })
+            .map(|(inner_edge, inner_path)| GraphPath {
+                edges: once(inner_edge.to_owned())
+                    .chain(inner_path.edges.iter())
+                    .cloned()
+                    .collect_vec(),
             });
 
         let end = left_edges
@@ -264,170 +787,76 @@ impl DataCycle for Node {
             .filter(|(inner_edge, inner_path)| {
                 inner_edge.from == inner_path.edges.last().expect("nonempty path").from
             })
-            .map(|(inner_edge, inner_path)| {
-                Data::GraphPath(GraphPath {
-                    edges: inner_path
-                        .edges
-                        .iter()
-                        .chain(once(inner_edge.to_owned()))
-                        .cloned()
-                        .collect_vec(),
-                })
+            .map(|(inner_edge, inner_path)| GraphPath {
+                edges: inner_path
+                    .edges
+                    .iter()
+                    .chain(once(inner_edge.to_owned()))
+                    .cloned()
+                    .collect_vec(),
             });
 
         new_minimal_path.chain(beginning).chain(end).collect_vec()
     }
 
+    fn store(&self, db: &mut Self::Database, data: &Node) -> Option<DatabaseLocation> {
+        insert(db, |t| &t.nodes, |t| &mut t.nodes, "nodes", data)
+    }
+
     fn save(
         &self,
         db: &mut Self::Database,
-        new_data: Vec<&Data>,
-    ) -> Vec<std::option::Option<DatabaseLocation>> {
-        new_data
-            .into_iter()
-            .map(|datum| {
-                let data;
-                if let Data::GraphPath(path) = datum {
-                    data = path;
-                } else {
-                    panic!("node should only be saving paths")
-                }
-
-                let mut hasher = DefaultHasher::new();
-                data.hash(&mut hasher);
-                let hash = hasher.finish();
-
-                let existed = db.paths.insert(hash, data.to_owned());
-
-                match existed {
-                    Some(_) => None,
-                    None => Some(DatabaseLocation {
-                        data_type: "paths".to_string(),
-                        hash,
-                    }),
-                }
-            })
-            .collect_vec()
+        new_data: Vec<&GraphPath>,
+    ) -> Vec<Option<DatabaseLocation>> {
+        save_paths(db, new_data)
     }
 
     fn public(&self) -> bool {
         false
     }
+
+    // shorter paths first
+    fn priority(&self, output: &GraphPath) -> usize {
+        output.edges.len()
+    }
 }
 
-impl DataCycle for Edge {
-    type Database = GraphData;
+impl TypedCycle for Edge {
+    type Database = GraphDb;
     type DataRoute = DatabaseLocation;
     type Data = Data;
+    type Input = Edge;
+    type Friend = PathPiece;
+    type Output = GraphPath;
 
     fn stop_categorically(&self, db: &Self::Database) -> bool {
-        db.nodes.is_empty()
+        db.is_empty(|t| &t.nodes)
     }
 
-    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Data> {
-        db.edges
-            .get(&route.hash)
-            .map(|edge| Data::Edge(edge.to_owned()))
+    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Edge> {
+        db.get(|t| &t.edges, &route.hash).cloned()
     }
 
-    fn get_friends(&self, db: &Self::Database, route: &Self::DataRoute) -> Vec<Self::Data> {
-        // All edges must be inhabited to be friends
-        // edge : edge
-        // to left
-        // to right
-
-        // edge : path
-        // path : edge
-
-        let data = self
-            .get_data(db, route)
-            .expect("Do not get friends of nonexistent data");
-
-        let missing_edge;
-        if let Data::Edge(edge) = data {
-            missing_edge = edge;
-        } else {
-            panic!("route in data cycle must point to an edge")
-        };
-
-        let left_node = missing_edge.from;
-        let right_node = missing_edge.to;
-
-        let edges_to_left = db
-            .edges
-            .iter()
-            .filter(|(_, inner_edge)| inner_edge.to == left_node)
-            .filter(|(_, inner_edge)| db.nodes.values().contains(&inner_edge.from))
-            .map(|(_, inner_edge)| inner_edge)
-            .cloned()
-            .map(|inner_edge| Data::Edge(inner_edge));
-
-        let edges_to_right = db
-            .edges
-            .iter()
-            .filter(|(_, inner_edge)| inner_edge.from == right_node)
-            .filter(|(_, inner_edge)| db.nodes.values().contains(&inner_edge.to))
-            .map(|(_, inner_edge)| inner_edge)
-            .cloned()
-            .map(|inner_edge| Data::Edge(inner_edge));
-
-        let paths_to_right = db
-            .paths
-            .iter()
-            .filter(|(_, inner_path)| {
-                inner_path.edges.first().expect("nonempty path").from == right_node
-            })
-            .map(|(_, inner_path)| inner_path)
-            .cloned()
-            .map(|inner_path| Data::GraphPath(inner_path));
-
-        let paths_to_left = db
-            .paths
-            .iter()
-            .filter(|(_, inner_path)| {
-                inner_path.edges.last().expect("nonempty path").to == left_node
-            })
-            .map(|(_, inner_path)| inner_path)
-            .cloned()
-            .map(|inner_path| Data::GraphPath(inner_path));
-
-        edges_to_left
-            .chain(edges_to_right)
-            .chain(paths_to_right)
-            .chain(paths_to_left)
-            .collect_vec()
+    fn get_friends(&self, db: &Self::Database, missing_edge: &Edge) -> Vec<PathPiece> {
+        edge_friends(db, missing_edge).expect("in-memory tables can't fail")
     }
 
-    fn stop_data(&self, data: &Self::Data, db: &Self::Database) -> bool {
-        let missing_edge;
-        if let Data::Edge(edge) = data {
-            missing_edge = edge;
-        } else {
-            panic!("route in data cycle must point to an edge")
-        };
-
-        !(db.nodes.values().contains(&missing_edge.from)
-            && db.nodes.values().contains(&missing_edge.to))
+    fn stop_data(&self, missing_edge: &Edge, db: &Self::Database) -> bool {
+        !(db.values(|t| &t.nodes).contains(&missing_edge.from)
+            && db.values(|t| &t.nodes).contains(&missing_edge.to))
     }
 
-    fn stop_friends(&self, friends: &Vec<Self::Data>) -> bool {
+    fn stop_friends(&self, friends: &[PathPiece]) -> bool {
         friends.is_empty()
     }
 
-    fn search(&self, data: &Self::Data, friends: &Vec<Self::Data>) -> Vec<Self::Data> {
+    fn search(&self, missing_edge: &Edge, friends: &[PathPiece]) -> Vec<GraphPath> {
         // edge : edge
         // to left
         // to right
 
         // edge : path
         // path : edge
-        let missing_edge;
-        if let Data::Edge(edge) = data {
-            missing_edge = edge;
-        } else {
-            panic!("route in data cycle must point to an edge")
-        };
-
         let right_node = missing_edge.to.clone();
 
         let mut left_paths: Vec<&GraphPath> = vec![];
@@ -436,8 +865,7 @@ impl DataCycle for Edge {
         let mut right_edges: Vec<&Edge> = vec![];
         for friend in friends {
             match friend {
-                Data::Node(_) => panic!("edges are not friends with nodes"),
-                Data::Edge(edge) => {
+                PathPiece::Edge(edge) => {
                     if edge.from == right_node {
                         left_edges.push(edge);
                     } else {
@@ -445,8 +873,7 @@ impl DataCycle for Edge {
                         right_edges.push(edge);
                     }
                 }
-                Data::InputFile(_) => panic!("edges are not friends with input files"),
-                Data::GraphPath(path) => {
+                PathPiece::GraphPath(path) => {
                     if path.edges.first().expect("nonempty").from == right_node {
                         left_paths.push(path);
                     } else {
@@ -457,33 +884,25 @@ impl DataCycle for Edge {
             }
         }
 
-        let edge_friend_then_missing = right_edges.iter().map(|inner_edge| {
-            Data::GraphPath(GraphPath {
-                edges: vec![inner_edge.to_owned().to_owned(), missing_edge.to_owned()],
-            })
+        let edge_friend_then_missing = right_edges.iter().map(|inner_edge| GraphPath {
+            edges: vec![inner_edge.to_owned().to_owned(), missing_edge.to_owned()],
         });
-        let missing_then_edge_friend = left_edges.iter().map(|inner_edge| {
-            Data::GraphPath(GraphPath {
-                edges: vec![missing_edge.to_owned(), inner_edge.to_owned().to_owned()],
-            })
+        let missing_then_edge_friend = left_edges.iter().map(|inner_edge| GraphPath {
+            edges: vec![missing_edge.to_owned(), inner_edge.to_owned().to_owned()],
         });
 
-        let path_friend_then_missing = right_paths.iter().map(|inner_path| {
-            Data::GraphPath(GraphPath {
-                edges: inner_path
-                    .edges
-                    .iter()
-                    .cloned()
-                    .chain(once(missing_edge.clone()))
-                    .collect_vec(),
-            })
+        let path_friend_then_missing = right_paths.iter().map(|inner_path| GraphPath {
+            edges: inner_path
+                .edges
+                .iter()
+                .cloned()
+                .chain(once(missing_edge.clone()))
+                .collect_vec(),
         });
-        let missing_then_path_friend = left_paths.iter().map(|inner_path| {
-            Data::GraphPath(GraphPath {
-                edges: once(missing_edge.clone())
-                    .chain(inner_path.edges.iter().cloned())
-                    .collect_vec(),
-            })
+        let missing_then_path_friend = left_paths.iter().map(|inner_path| GraphPath {
+            edges: once(missing_edge.clone())
+                .chain(inner_path.edges.iter().cloned())
+                .collect_vec(),
         });
 
         edge_friend_then_missing
@@ -493,95 +912,74 @@ impl DataCycle for Edge {
             .collect_vec()
     }
 
+    fn store(&self, db: &mut Self::Database, data: &Edge) -> Option<DatabaseLocation> {
+        insert(db, |t| &t.edges, |t| &mut t.edges, "edges", data)
+    }
+
     fn save(
         &self,
         db: &mut Self::Database,
-        new_data: Vec<&Data>,
-    ) -> Vec<std::option::Option<DatabaseLocation>> {
-        new_data
-            .into_iter()
-            .map(|datum| {
-                let data;
-                if let Data::GraphPath(path) = datum {
-                    data = path;
-                } else {
-                    panic!("edge should only be saving paths")
-                }
-
-                let mut hasher = DefaultHasher::new();
-                data.hash(&mut hasher);
-                let hash = hasher.finish();
-
-                let existed = db.paths.insert(hash, data.to_owned());
-
-                match existed {
-                    Some(_) => None,
-                    None => Some(DatabaseLocation {
-                        data_type: "paths".to_string(),
-                        hash,
-                    }),
-                }
-            })
-            .collect_vec()
+        new_data: Vec<&GraphPath>,
+    ) -> Vec<Option<DatabaseLocation>> {
+        save_paths(db, new_data)
     }
 
     fn public(&self) -> bool {
         false
     }
+
+    // shorter paths first
+    fn priority(&self, output: &GraphPath) -> usize {
+        output.edges.len()
+    }
 }
 
-impl DataCycle for InputFile {
-    type Database = GraphData;
+impl TypedCycle for InputFile {
+    type Database = GraphDb;
     type DataRoute = DatabaseLocation;
     type Data = Data;
+    type Input = InputFile;
+    type Friend = Infallible;
+    type Output = Element;
 
     fn stop_categorically(&self, _db: &Self::Database) -> bool {
         false
     }
 
-    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Data> {
-        db.input_files
-            .get(&route.hash)
-            .map(|input_file| Data::InputFile(input_file.to_owned()))
+    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<InputFile> {
+        db.get(|t| &t.input_files, &route.hash).cloned()
     }
 
-    fn get_friends(&self, _db: &Self::Database, _route: &Self::DataRoute) -> Vec<Self::Data> {
+    fn get_friends(&self, _db: &Self::Database, _data: &InputFile) -> Vec<Infallible> {
         vec![]
     }
 
-    fn stop_data(&self, _data: &Self::Data, _db: &Self::Database) -> bool {
+    fn stop_data(&self, _data: &InputFile, _db: &Self::Database) -> bool {
         false
     }
 
-    fn stop_friends(&self, _friends: &Vec<Self::Data>) -> bool {
+    fn stop_friends(&self, _friends: &[Infallible]) -> bool {
         false
     }
 
-    fn search(&self, data: &Self::Data, _friends: &Vec<Self::Data>) -> Vec<Self::Data> {
-        let input_file;
-        if let Data::InputFile(file) = data {
-            input_file = file;
-        } else {
-            panic!("route in data cycle must point to an input file")
-        };
-
+    fn search(&self, input_file: &InputFile, _friends: &[Infallible]) -> Vec<Element> {
         let (nodes, edges) = input_file
             .contents
             .split("--")
             .collect_tuple()
             .expect("file must be formatted nodes -- edges");
 
-        let new_nodes = nodes.split("\n").map(|label| {
-            Data::Node(Node {
+        let new_nodes = nodes.split('\n').map(|label| {
+            Element::Node(Node {
                 label: label.to_string(),
             })
         });
-        let new_edges = edges.split("\n").map(|edge| {
+        let new_edges = edges.split('\n').map(|edge| {
             let (first, second) = edge
                 .split("->")
                 .collect_tuple()
                 .expect("nodes must be formatted like label1->label2");
-            Data::Edge(Edge {
+            Element::Edge(Edge {
                 from: Node {
                     label: first.to_owned(),
                 },
@@ -593,46 +991,26 @@ impl DataCycle for InputFile {
         new_nodes.chain(new_edges).collect_vec()
     }
 
+    fn store(&self, db: &mut Self::Database, data: &InputFile) -> Option<DatabaseLocation> {
+        insert(
+            db,
+            |t| &t.input_files,
+            |t| &mut t.input_files,
+            "input_files",
+            data,
+        )
+    }
+
     fn save(
         &self,
         db: &mut Self::Database,
-        new_data: Vec<&Data>,
-    ) -> Vec<std::option::Option<DatabaseLocation>> {
+        new_data: Vec<&Element>,
+    ) -> Vec<Option<DatabaseLocation>> {
         new_data
             .into_iter()
             .map(|datum| match datum {
-                Data::Node(node) => {
-                    let mut hasher = DefaultHasher::new();
-                    node.hash(&mut hasher);
-                    let hash = hasher.finish();
-
-                    let existed = db.nodes.insert(hash, node.to_owned());
-
-                    match existed {
-                        Some(_) => None,
-                        None => Some(DatabaseLocation {
-                            data_type: "nodes".to_string(),
-                            hash,
-                        }),
-                    }
-                }
-                Data::Edge(edge) => {
-                    let mut hasher = DefaultHasher::new();
-                    edge.hash(&mut hasher);
-                    let hash = hasher.finish();
-
-                    let existed = db.edges.insert(hash, edge.to_owned());
-
-                    match existed {
-                        Some(_) => None,
-                        None => Some(DatabaseLocation {
-                            data_type: "edges".to_string(),
-                            hash,
-                        }),
-                    }
-                }
-                Data::InputFile(_) => panic!("input file does not produce input files"),
-                Data::GraphPath(_) => panic!("input file does not produce graph paths"),
+                Element::Node(node) => insert(db, |t| &t.nodes, |t| &mut t.nodes, "nodes", node),
+                Element::Edge(edge) => insert(db, |t| &t.edges, |t| &mut t.edges, "edges", edge),
             })
             .collect_vec()
     }
@@ -642,94 +1020,39 @@ impl DataCycle for InputFile {
     }
 }
 
-impl DataCycle for GraphPath {
-    type Database = GraphData;
+impl TypedCycle for GraphPath {
+    type Database = GraphDb;
     type DataRoute = DatabaseLocation;
     type Data = Data;
+    type Input = GraphPath;
+    type Friend = PathPiece;
+    type Output = GraphPath;
 
     fn stop_categorically(&self, db: &Self::Database) -> bool {
-        db.nodes.is_empty() || db.edges.is_empty()
+        db.is_empty(|t| &t.nodes) || db.is_empty(|t| &t.edges)
     }
 
-    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Data> {
-        db.paths
-            .get(&route.hash)
-            .map(|graph_path| Data::GraphPath(graph_path.to_owned()))
+    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<GraphPath> {
+        db.get(|t| &t.paths, &route.hash).cloned()
+    }
+
+    fn get_friends(&self, db: &Self::Database, missing_graph: &GraphPath) -> Vec<PathPiece> {
+        path_friends(db, missing_graph).expect("in-memory tables can't fail")
+    }
+
+    fn stop_data(&self, _data: &GraphPath, _db: &Self::Database) -> bool {
+        false
+    }
+
+    fn stop_friends(&self, _friends: &[PathPiece]) -> bool {
+        false
     }
 
-    fn get_friends(&self, db: &Self::Database, route: &Self::DataRoute) -> Vec<Self::Data> {
+    fn search(&self, missing_path: &GraphPath, friends: &[PathPiece]) -> Vec<GraphPath> {
         // this path : other path
         // other path : this path
         // edge : this path
         // this path : edge
-        let data = self
-            .get_data(db, route)
-            .expect("Do not get friends of nonexistent data");
-
-        let missing_graph;
-        if let Data::GraphPath(graph) = data {
-            missing_graph = graph;
-        } else {
-            panic!("route in data cycle must point to a graph")
-        };
-
-        let start_node = missing_graph
-            .edges
-            .first()
-            .expect("nonempty")
-            .from
-            .to_owned();
-        let end_node = missing_graph.edges.last().expect("nonempty").to.to_owned();
-
-        let this_then_friend = db
-            .paths
-            .values()
-            .filter(|friend| friend.edges.last().expect("nonempty").to == start_node)
-            .map(|friend| Data::GraphPath(friend.clone()));
-        let friend_then_this = db
-            .paths
-            .values()
-            .filter(|friend| friend.edges.first().expect("nonempty").from == end_node)
-            .map(|friend| Data::GraphPath(friend.clone()));
-
-        let edge_then_path = db
-            .edges
-            .values()
-            .filter(|friend| friend.to == start_node && db.nodes.values().contains(&friend.from))
-            .map(|friend| Data::Edge(friend.clone()));
-        let path_then_edge = db
-            .edges
-            .values()
-            .filter(|friend| friend.to == start_node && db.nodes.values().contains(&friend.to))
-            .map(|friend| Data::Edge(friend.clone()));
-        this_then_friend
-            .chain(friend_then_this)
-            .chain(edge_then_path)
-            .chain(path_then_edge)
-            .collect_vec()
-    }
-
-    fn stop_data(&self, _data: &Self::Data, _db: &Self::Database) -> bool {
-        false
-    }
-
-    fn stop_friends(&self, _friends: &Vec<Self::Data>) -> bool {
-        false
-    }
-
-    fn search(&self, data: &Self::Data, friends: &Vec<Self::Data>) -> Vec<Self::Data> {
-        // this path : other path
-        // other path : this path
-        // edge : this path
-        // this path : edge
-
-        let missing_path;
-        if let Data::GraphPath(path) = data {
-            missing_path = path;
-        } else {
-            panic!("route in data cycle must point to a path")
-        };
-
         let right_node = missing_path.edges.last().expect("nonempty").to.clone();
 
         let mut left_paths: Vec<&GraphPath> = vec![];
@@ -738,8 +1061,7 @@ impl DataCycle for GraphPath {
         let mut right_edges: Vec<&Edge> = vec![];
         for friend in friends {
             match friend {
-                Data::Node(_) => panic!("paths are not friends with nodes"),
-                Data::Edge(edge) => {
+                PathPiece::Edge(edge) => {
                     if edge.from == right_node {
                         left_edges.push(edge);
                     } else {
@@ -747,8 +1069,7 @@ impl DataCycle for GraphPath {
                         right_edges.push(edge);
                     }
                 }
-                Data::InputFile(_) => panic!("paths are not friends with input files"),
-                Data::GraphPath(path) => {
+                PathPiece::GraphPath(path) => {
                     if path.edges.first().expect("nonempty").from == right_node {
                         left_paths.push(path);
                     } else {
@@ -759,45 +1080,37 @@ impl DataCycle for GraphPath {
             }
         }
 
-        let this_then_friend = right_paths.iter().map(|friend| {
-            Data::GraphPath(GraphPath {
-                edges: missing_path
-                    .edges
-                    .iter()
-                    .chain(friend.edges.iter())
-                    .cloned()
-                    .collect_vec(),
-            })
+        let this_then_friend = right_paths.iter().map(|friend| GraphPath {
+            edges: missing_path
+                .edges
+                .iter()
+                .chain(friend.edges.iter())
+                .cloned()
+                .collect_vec(),
         });
-        let friend_then_this = left_paths.iter().map(|friend| {
-            Data::GraphPath(GraphPath {
-                edges: friend
-                    .edges
-                    .iter()
-                    .chain(missing_path.edges.iter())
-                    .cloned()
-                    .collect_vec(),
-            })
+        let friend_then_this = left_paths.iter().map(|friend| GraphPath {
+            edges: friend
+                .edges
+                .iter()
+                .chain(missing_path.edges.iter())
+                .cloned()
+                .collect_vec(),
         });
 
-        let this_then_edge = right_edges.iter().map(|friend| {
-            Data::GraphPath(GraphPath {
-                edges: missing_path
-                    .edges
-                    .iter()
-                    .chain(once(friend.clone()))
-                    .cloned()
-                    .collect_vec(),
-            })
+        let this_then_edge = right_edges.iter().map(|friend| GraphPath {
+            edges: missing_path
+                .edges
+                .iter()
+                .chain(once(*friend))
+                .cloned()
+                .collect_vec(),
         });
 
-        let edge_then_this = left_edges.iter().map(|friend| {
-            Data::GraphPath(GraphPath {
-                edges: once(friend.clone())
-                    .chain(missing_path.edges.iter())
-                    .cloned()
-                    .collect_vec(),
-            })
+        let edge_then_this = left_edges.iter().map(|friend| GraphPath {
+            edges: once(*friend)
+                .chain(missing_path.edges.iter())
+                .cloned()
+                .collect_vec(),
         });
 
         this_then_friend
@@ -807,54 +1120,158 @@ impl DataCycle for GraphPath {
             .collect_vec()
     }
 
+    fn store(&self, db: &mut Self::Database, data: &GraphPath) -> Option<DatabaseLocation> {
+        insert(db, |t| &t.paths, |t| &mut t.paths, "paths", data)
+    }
+
     fn save(
         &self,
         db: &mut Self::Database,
-        new_data: Vec<&Data>,
-    ) -> Vec<std::option::Option<DatabaseLocation>> {
-        new_data
-            .into_iter()
-            .map(|datum| match datum {
-                Data::Node(_) => panic!("graph path does not produce nodes"),
-                Data::GraphPath(path) => {
-                    let mut hasher = DefaultHasher::new();
-                    path.hash(&mut hasher);
-                    let hash = hasher.finish();
-
-                    let existed = db.paths.insert(hash, path.to_owned());
-
-                    match existed {
-                        Some(_) => None,
-                        None => Some(DatabaseLocation {
-                            data_type: "paths".to_string(),
-                            hash,
-                        }),
-                    }
-                }
-                Data::InputFile(_) => panic!("graph path does not produce input files"),
-                Data::Edge(_) => panic!("graph path does not produce edges"),
-            })
-            .collect_vec()
+        new_data: Vec<&GraphPath>,
+    ) -> Vec<Option<DatabaseLocation>> {
+        save_paths(db, new_data)
     }
 
     fn public(&self) -> bool {
         false
     }
+
+    // shorter paths first
+    fn priority(&self, output: &GraphPath) -> usize {
+        output.edges.len()
+    }
+}
+
+struct Holder {
+    frontier: FrontierKind,
+    budget: Budget,
+    steal_interval: Option<usize>,
+    checkpoint_interval: Option<usize>,
+    seen: RefCell<Box<dyn SeenSet>>,
+    // shared with `main`, which reports what was stored once the worker is done
+    store: Rc<FsStore>,
+    backend: DatabaseBackend,
+    // the broker the job, merge and steal queues live on: RabbitMQ if set, otherwise beanstalkd
+    amqp_url: Option<String>,
+}
+
+// a stored job database, read a record at a time so merges classify routes without loading it
+enum StoredGraph {
+    Shard(Shard),
+    Kv(DatabaseStore),
+    Sqlite(SqliteStore),
+}
+
+impl StoredGraph {
+    fn get<V: DeserializeOwned>(&self, table: &str, key: u64) -> Result<Option<V>, anyhow::Error> {
+        match self {
+            StoredGraph::Shard(shard) => shard.get_value(table, key),
+            StoredGraph::Kv(kv) => kv.get_value(table, key),
+            StoredGraph::Sqlite(sql) => sql.get_value(table, key),
+        }
+    }
+
+    fn each<V: DeserializeOwned>(
+        &self,
+        table: &str,
+        visit: &mut dyn FnMut(&V),
+    ) -> Result<(), anyhow::Error> {
+        let values: Box<dyn Iterator<Item = Result<(u64, V), anyhow::Error>>> = match self {
+            StoredGraph::Shard(shard) => Box::new(shard.values(table)),
+            StoredGraph::Kv(kv) => Box::new(kv.values(table)?),
+            StoredGraph::Sqlite(sql) => Box::new(sql.values(table)?),
+        };
+        for value in values {
+            visit(&value?.1);
+        }
+        Ok(())
+    }
 }
 
-struct Holder {}
+impl GraphTables for StoredGraph {
+    fn has_node(&self, node: &Node) -> Result<bool, anyhow::Error> {
+        Ok(self.get::<Node>("nodes", content_id(node))?.is_some())
+    }
+
+    fn each_edge(&self, visit: &mut dyn FnMut(&Edge)) -> Result<(), anyhow::Error> {
+        self.each("edges", visit)
+    }
+
+    fn each_path(&self, visit: &mut dyn FnMut(&GraphPath)) -> Result<(), anyhow::Error> {
+        self.each("paths", visit)
+    }
+}
+
+impl RouteLookup<Holder> for StoredGraph {
+    fn data(
+        &self,
+        _cycle: &RegistryCycle<Holder>,
+        route: &DatabaseLocation,
+    ) -> Result<Option<Data>, anyhow::Error> {
+        let hash = route.hash;
+        Ok(match route.data_type.as_str() {
+            "nodes" => self.get("nodes", hash)?.map(Data::Node),
+            "edges" => self.get("edges", hash)?.map(Data::Edge),
+            "input_files" => self.get("input_files", hash)?.map(Data::InputFile),
+            _ => self.get("paths", hash)?.map(Data::GraphPath),
+        })
+    }
+
+    fn friends(
+        &self,
+        cycle: &RegistryCycle<Holder>,
+        route: &DatabaseLocation,
+    ) -> Result<Vec<Data>, anyhow::Error> {
+        let friends = match self.data(cycle, route)? {
+            Some(Data::Node(node)) => node_friends(self, &node)?,
+            Some(Data::Edge(edge)) => edge_friends(self, &edge)?,
+            Some(Data::GraphPath(path)) => path_friends(self, &path)?,
+            Some(Data::InputFile(_)) | None => vec![],
+        };
+        Ok(friends.into_iter().map(Variant::into_data).collect())
+    }
+}
+
+// how job databases are kept: as shards rewritten on every merge, or as key-value or SQLite
+// databases a merge ingests the second of into the first, in place
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum DatabaseBackend {
+    #[default]
+    Shard,
+    Kv,
+    Sqlite,
+}
+
+impl std::str::FromStr for DatabaseBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shard" => Ok(DatabaseBackend::Shard),
+            "kv" => Ok(DatabaseBackend::Kv),
+            "sqlite" => Ok(DatabaseBackend::Sqlite),
+            _ => anyhow::bail!("unknown database store {s}, expected shard, kv or sqlite"),
+        }
+    }
+}
+
+// beanstalkd tubes, or AMQP queues of the same names
+enum GlobalQueue {
+    Beanstalk(Beanstalkc),
+    Amqp(Box<AmqpQueue>),
+}
 
 impl Registry for Holder {
-    type Database = GraphData;
-    type Location = String;
-    type GlobalQueueLocation = Beanstalkc;
+    type Database = GraphDb;
+    type Location = PathBuf;
+    type GlobalQueueLocation = GlobalQueue;
     type DataRoute = DatabaseLocation;
     type JobReceipt = u64;
-    type LocalQueue = Vec<DatabaseLocation>;
+    type LocalQueue = LocalFrontier<DatabaseLocation>;
     type Data = Data;
 
     fn create_db(&self) -> Self::Database {
-        GraphData::default()
+        GraphDb::default()
     }
 
     fn worker_name(&self) -> String {
@@ -866,25 +1283,31 @@ impl Registry for Holder {
         worker_name: String,
         random_string: String,
     ) -> Result<Self::Location, anyhow::Error> {
-        let filename = "database".to_owned() + &worker_name + &random_string;
-
-        Ok(filename)
+        Ok(self
+            .store
+            .location("database", &worker_name, &random_string))
     }
 
     fn create_global_queue(&self) -> Result<Self::GlobalQueueLocation, anyhow::Error> {
-        let conn = Beanstalkc::new().connect()?;
-        Ok(conn)
+        Ok(match &self.amqp_url {
+            Some(url) => GlobalQueue::Amqp(Box::new(AmqpQueue::open(url)?)),
+            None => GlobalQueue::Beanstalk(Beanstalkc::new().connect()?),
+        })
     }
 
     fn consume_global(
         &self,
         queue: &mut Self::GlobalQueueLocation,
     ) -> Result<(Self::Data, Self::JobReceipt), anyhow::Error> {
-        queue.watch("jobs")?;
-
-        let job = queue.reserve()?;
-        let ans = bincode::deserialize(job.body())?;
-        let reciept = job.id();
+        let (reciept, body) = match queue {
+            GlobalQueue::Beanstalk(queue) => {
+                queue.watch("jobs")?;
+                let job = queue.reserve()?;
+                (job.id(), job.body().to_vec())
+            }
+            GlobalQueue::Amqp(queue) => queue.reserve("jobs")?,
+        };
+        let ans = self.store.decode("job", &body)?;
 
         Ok((ans, reciept))
     }
@@ -895,28 +1318,33 @@ impl Registry for Holder {
         queue: &mut Self::GlobalQueueLocation,
         priority: usize,
     ) -> Result<Self::JobReceipt, anyhow::Error> {
-        let to_put = bincode::serialize(&data)?;
-        queue.use_tube("jobs")?;
-        let res = queue.put(
-            &to_put,
-            priority.try_into().unwrap(),
-            Duration::from_secs(0),
-            Duration::from_secs(10),
-        )?;
+        let to_put = self.store.encode(&data)?;
+        let res = match queue {
+            GlobalQueue::Beanstalk(queue) => {
+                queue.use_tube("jobs")?;
+                queue.put(
+                    &to_put,
+                    priority.try_into().unwrap(),
+                    Duration::from_secs(0),
+                    Duration::from_secs(10),
+                )?
+            }
+            GlobalQueue::Amqp(queue) => queue.put("jobs", &to_put, priority)?,
+        };
 
         Ok(res)
     }
 
     fn create_local_queue(&self) -> Self::LocalQueue {
-        vec![]
+        LocalFrontier::new(self.frontier)
     }
 
     fn consume_local(&self, queue: &mut Self::LocalQueue) -> Option<Self::DataRoute> {
         queue.pop()
     }
 
-    fn produce_local(&self, queue: &mut Vec<DatabaseLocation>, loc: Self::DataRoute) {
-        queue.push(loc)
+    fn produce_local(&self, queue: &mut Self::LocalQueue, loc: Self::DataRoute, priority: usize) {
+        queue.push(loc, priority)
     }
 
     fn queue_location(
@@ -924,9 +1352,7 @@ impl Registry for Holder {
         worker_name: String,
         unique_string: String,
     ) -> Result<Self::Location, anyhow::Error> {
-        let filename = "queue".to_owned() + &worker_name + &unique_string;
-
-        Ok(filename)
+        Ok(self.store.location("queue", &worker_name, &unique_string))
     }
 
     fn write_local_queue(
@@ -934,80 +1360,125 @@ impl Registry for Holder {
         loc: &Self::Location,
         queue: &Self::LocalQueue,
     ) -> Result<(), anyhow::Error> {
-        let serialized = bincode::serialize(&queue)?;
-
-        let mut f = File::create(loc)?;
-        f.write_all(&serialized)?;
-        Ok(())
+        self.store.write(loc, queue)
     }
 
     fn write_db(&self, loc: &Self::Location, db: &Self::Database) -> Result<(), anyhow::Error> {
-        let serialized = bincode::serialize(&db)?;
-
-        let mut f = File::create(loc)?;
-        f.write_all(&serialized)?;
-        Ok(())
+        let data = db.delta();
+        match self.backend {
+            DatabaseBackend::Shard => self.store.write_shard(loc, |shard| {
+                shard.write_table("nodes", &data.nodes)?;
+                shard.write_table("edges", &data.edges)?;
+                shard.write_table("input_files", &data.input_files)?;
+                shard.write_table("paths", &data.paths)?;
+                shard.write_table("shortest", &data.shortest)
+            }),
+            DatabaseBackend::Kv => self.store.write_database(loc, |kv| {
+                kv.write_table("nodes", &data.nodes)?;
+                kv.write_table("edges", &data.edges)?;
+                kv.write_table("input_files", &data.input_files)?;
+                kv.write_table("paths", &data.paths)?;
+                kv.write_table("shortest", &data.shortest)
+            }),
+            DatabaseBackend::Sqlite => self.store.write_sqlite(loc, |sql| {
+                sql.write_table("nodes", &data.nodes)?;
+                sql.write_table("edges", &data.edges)?;
+                sql.write_table("input_files", &data.input_files)?;
+                sql.write_table("paths", &data.paths)?;
+                sql.write_table("shortest", &data.shortest)
+            }),
+        }
     }
 
     fn read_queue(&self, loc: &Self::Location) -> Result<Self::LocalQueue, anyhow::Error> {
-        let mut f = File::create(loc)?;
-        let mut buf = vec![];
-        f.read_to_end(&mut buf)?;
-        let deserialized = bincode::deserialize(&buf)?;
-
-        Ok(deserialized)
+        self.store.read("queue", loc)
     }
 
     fn read_db(&self, loc: &Self::Location) -> Result<Self::Database, anyhow::Error> {
-        let mut f = File::create(loc)?;
-        let mut buf = vec![];
-        f.read_to_end(&mut buf)?;
-        let deserialized = bincode::deserialize(&buf)?;
-
-        Ok(deserialized)
+        let data = match self.backend {
+            DatabaseBackend::Shard => {
+                let shard = self.store.open_shard(loc)?;
+                GraphData {
+                    nodes: shard.values("nodes").collect::<Result<_, _>>()?,
+                    edges: shard.values("edges").collect::<Result<_, _>>()?,
+                    input_files: shard.values("input_files").collect::<Result<_, _>>()?,
+                    paths: shard.values("paths").collect::<Result<_, _>>()?,
+                    shortest: shard.values("shortest").collect::<Result<_, _>>()?,
+                }
+            }
+            DatabaseBackend::Kv => {
+                let kv = self.store.open_database(loc)?;
+                let data = GraphData {
+                    nodes: kv.values("nodes")?.collect::<Result<_, _>>()?,
+                    edges: kv.values("edges")?.collect::<Result<_, _>>()?,
+                    input_files: kv.values("input_files")?.collect::<Result<_, _>>()?,
+                    paths: kv.values("paths")?.collect::<Result<_, _>>()?,
+                    shortest: kv.values("shortest")?.collect::<Result<_, _>>()?,
+                };
+                data
+            }
+            DatabaseBackend::Sqlite => {
+                let sql = self.store.open_sqlite(loc)?;
+                let data = GraphData {
+                    nodes: sql.values("nodes")?.collect::<Result<_, _>>()?,
+                    edges: sql.values("edges")?.collect::<Result<_, _>>()?,
+                    input_files: sql.values("input_files")?.collect::<Result<_, _>>()?,
+                    paths: sql.values("paths")?.collect::<Result<_, _>>()?,
+                    shortest: sql.values("shortest")?.collect::<Result<_, _>>()?,
+                };
+                data
+            }
+        };
+        Ok(Overlay::detached(data))
     }
 
     fn consume_merge_event(
         &self,
         queue: &mut Self::GlobalQueueLocation,
-    ) -> Result<
-        Option<(
-            Self::Location,
-            Self::Location,
-            Self::JobReceipt,
-            Self::Location,
-            Self::Location,
-            Self::JobReceipt,
-        )>,
-        anyhow::Error,
-    > {
-        let binding = queue.stats_tube("merges")?;
-        let count = binding
-            .get("current-jobs-ready")
-            .context("cannot get current jobs ready")?;
-        if count == "0" || count == "1" {
-            return Ok(None);
-        }
-
-        queue.watch("merges")?;
-
-        let first_job = queue.reserve()?;
-        let (first_db, first_queue) = bincode::deserialize(first_job.body())?;
-        let first_receipt = first_job.id();
+    ) -> Result<Option<MergeEvent<Self::Location, Self::JobReceipt>>, anyhow::Error> {
+        let ((first_receipt, first_body), (second_receipt, second_body)) = match queue {
+            GlobalQueue::Beanstalk(queue) => {
+                let binding = queue.stats_tube("merges")?;
+                let count = binding
+                    .get("current-jobs-ready")
+                    .context("cannot get current jobs ready")?;
+                if count == "0" || count == "1" {
+                    return Ok(None);
+                }
 
-        let second_job = queue.reserve()?;
-        let (second_db, second_queue) = bincode::deserialize(second_job.body())?;
-        let second_receipt = second_job.id();
+                queue.watch("merges")?;
+                let first_job = queue.reserve()?;
+                let first = (first_job.id(), first_job.body().to_vec());
+                let second_job = queue.reserve()?;
+                (first, (second_job.id(), second_job.body().to_vec()))
+            }
+            GlobalQueue::Amqp(queue) => {
+                if queue.len("merges")? < 2 {
+                    return Ok(None);
+                }
+                let Some(first) = queue.try_reserve("merges")? else {
+                    return Ok(None);
+                };
+                let Some(second) = queue.try_reserve("merges")? else {
+                    // another worker took the other one first
+                    queue.release(first.0)?;
+                    return Ok(None);
+                };
+                (first, second)
+            }
+        };
+        let (first_db, first_queue) = self.store.decode("merge", &first_body)?;
+        let (second_db, second_queue) = self.store.decode("merge", &second_body)?;
 
-        let tup = (
+        let event = MergeEvent {
             first_db,
             first_queue,
             first_receipt,
             second_db,
             second_queue,
             second_receipt,
-        );
-        let ans = Some(tup);
+        };
+        let ans = Some(event);
         Ok(ans)
     }
 
@@ -1017,46 +1488,97 @@ impl Registry for Holder {
         first_db_loc: Self::Location,
         first_queue_loc: Self::Location,
     ) -> Result<Self::JobReceipt, anyhow::Error> {
-        let to_put = bincode::serialize(&(first_db_loc, first_queue_loc))?;
-        queue.use_tube("merges")?;
-        let res = queue.put(&to_put, 0, Duration::from_secs(0), Duration::from_secs(100))?;
+        // recorded first, so a record is never missing for a job another worker may already hold
+        let record = merge_record(&self.store, &first_db_loc)?;
+        let announced = (first_db_loc, first_queue_loc);
+        self.store.write(&record, &announced)?;
+        let to_put = self.store.encode(&announced)?;
+        let mut put = || {
+            Ok(match queue {
+                GlobalQueue::Beanstalk(queue) => {
+                    queue.use_tube("merges")?;
+                    queue.put(&to_put, 0, Duration::from_secs(0), Duration::from_secs(100))?
+                }
+                GlobalQueue::Amqp(queue) => queue.put("merges", &to_put, 0)?,
+            })
+        };
+        let res = put();
+        if res.is_err() {
+            self.store.remove(&record)?;
+        }
+        res
+    }
 
-        Ok(res)
+    fn collapse_dbs(
+        &self,
+        db: Self::Database,
+        other: Self::Database,
+        metrics: &mut MergeMetrics,
+    ) -> Self::Database {
+        // shards only hold what their jobs added on top of the base
+        Overlay::detached(collapse_policies().collapse(
+            db.into_delta(),
+            other.into_delta(),
+            metrics,
+        ))
     }
 
-    fn collapse_dbs(&self, db: &Self::Database, other: &Self::Database) -> Self::Database {
-        // todo make this less cloney
-        let nodes = db
-            .nodes
-            .iter()
-            .chain(other.nodes.iter())
-            .map(|(x, y)| (x.clone(), y.clone()))
-            .collect();
-        let edges = db
-            .edges
-            .iter()
-            .chain(other.edges.iter())
-            .map(|(x, y)| (x.clone(), y.clone()))
-            .collect();
-        let input_files = db
-            .input_files
-            .iter()
-            .chain(other.input_files.iter())
-            .map(|(x, y)| (x.clone(), y.clone()))
-            .collect();
-        let paths = db
-            .paths
-            .iter()
-            .chain(other.paths.iter())
-            .map(|(x, y)| (x.clone(), y.clone()))
-            .collect();
-
-        GraphData {
-            nodes,
-            edges,
-            input_files,
-            paths,
+    fn collapse_db_files(
+        &self,
+        first: &Self::Location,
+        second: &Self::Location,
+        out: &Self::Location,
+        metrics: &mut MergeMetrics,
+    ) -> Result<bool, anyhow::Error> {
+        // the inputs are opened as stored, since records are copied without decoding them
+        self.store.download(first)?;
+        self.store.download(second)?;
+        if self.backend == DatabaseBackend::Kv {
+            let second = DatabaseStore::open(second)?;
+            // stores from an older schema version are migrated by reading them instead
+            let header = DatabaseStore::open(first)?.header();
+            if header != self.store.header() || second.header() != self.store.header() {
+                return Ok(false);
+            }
+            self.store.extend_database(first, out, |kv| {
+                kv.ingest(&second, &shard_policies(), metrics)
+            })?;
+            return Ok(true);
         }
+        if self.backend == DatabaseBackend::Sqlite {
+            let second = SqliteStore::open(second)?;
+            let header = SqliteStore::open(first)?.header();
+            if header != self.store.header() || second.header() != self.store.header() {
+                return Ok(false);
+            }
+            self.store.extend_sqlite(first, out, |sql| {
+                sql.ingest(&second, &shard_policies(), metrics)
+            })?;
+            return Ok(true);
+        }
+
+        let (first, second) = (Shard::open(first)?, Shard::open(second)?);
+        // shards from an older schema version are migrated by reading them instead
+        if first.header() != self.store.header() || second.header() != self.store.header() {
+            return Ok(false);
+        }
+
+        self.store.write_shard(out, |shard| {
+            merge_shards(&first, &second, shard, &shard_policies(), metrics)
+        })?;
+        Ok(true)
+    }
+
+    fn open_lookup(
+        &self,
+        loc: &Self::Location,
+    ) -> Result<Option<Box<dyn RouteLookup<Self> + '_>>, anyhow::Error> {
+        let stored = match self.backend {
+            DatabaseBackend::Shard => StoredGraph::Shard(self.store.open_shard(loc)?),
+            DatabaseBackend::Kv => StoredGraph::Kv(self.store.open_database(loc)?),
+            DatabaseBackend::Sqlite => StoredGraph::Sqlite(self.store.open_sqlite(loc)?),
+        };
+        Ok(Some(Box::new(stored)))
     }
 
     fn ack_global(
@@ -1064,7 +1586,10 @@ impl Registry for Holder {
         queue: &mut Self::GlobalQueueLocation,
         receipt: Self::JobReceipt,
     ) -> Result<(), anyhow::Error> {
-        let _res = queue.delete(receipt)?;
+        match queue {
+            GlobalQueue::Beanstalk(queue) => queue.delete(receipt)?,
+            GlobalQueue::Amqp(queue) => queue.ack(receipt)?,
+        }
         Ok(())
     }
 
@@ -1074,16 +1599,16 @@ impl Registry for Holder {
     ) -> Box<dyn DataCycle<Database = Self::Database, DataRoute = Self::DataRoute, Data = Self::Data>>
     {
         if route.data_type == "nodes" {
-            return Box::new(Node::default());
+            return Box::new(Typed(Node::default()));
         }
         if route.data_type == "edges" {
-            return Box::new(Edge::default());
+            return Box::new(Typed(Edge::default()));
         }
         if route.data_type == "input_files" {
-            return Box::new(InputFile::default());
+            Box::new(Typed(InputFile::default()))
         } else {
             // route.data_type == "paths" {
-            Box::new(GraphPath::default())
+            Box::new(Typed(GraphPath::default()))
         }
     }
 
@@ -1093,7 +1618,12 @@ impl Registry for Holder {
     }
 
     fn delete_db(&self, loc: Self::Location) -> Result<(), anyhow::Error> {
-        fs::remove_file(loc)?;
+        // a streamed merge moves the first key-value or SQLite database into its result
+        if !self.store.remove(&loc)? && self.backend == DatabaseBackend::Shard {
+            anyhow::bail!("{} was already deleted", loc.display());
+        }
+        // a shard is deleted once the merge that read it is acked
+        self.store.remove(&merge_record(&self.store, &loc)?)?;
         Ok(())
     }
 
@@ -1103,16 +1633,657 @@ impl Registry for Holder {
     ) -> Box<dyn DataCycle<Database = Self::Database, DataRoute = Self::DataRoute, Data = Self::Data>>
     {
         match data {
-            Data::Node(_) => Box::new(Node::default()),
-            Data::Edge(_) => Box::new(Edge::default()),
-            Data::InputFile(_) => Box::new(InputFile::default()),
-            Data::GraphPath(_) => Box::new(GraphPath::default()),
+            Data::Node(_) => Box::new(Typed(Node::default())),
+            Data::Edge(_) => Box::new(Typed(Edge::default())),
+            Data::InputFile(_) => Box::new(Typed(InputFile::default())),
+            Data::GraphPath(_) => Box::new(Typed(GraphPath::default())),
+        }
+    }
+
+    fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    fn db_size(&self, db: &Self::Database) -> usize {
+        let delta = db.delta();
+        delta.nodes.len()
+            + delta.edges.len()
+            + delta.input_files.len()
+            + delta.paths.len()
+            + delta.shortest.len()
+    }
+
+    fn local_queue_len(&self, queue: &Self::LocalQueue) -> usize {
+        queue.len()
+    }
+
+    fn has_global_work(
+        &self,
+        queue: &mut Self::GlobalQueueLocation,
+    ) -> Result<bool, anyhow::Error> {
+        let queue = match queue {
+            GlobalQueue::Beanstalk(queue) => queue,
+            GlobalQueue::Amqp(queue) => return Ok(!queue.is_empty("jobs")?),
+        };
+        let ready = match queue.stats_tube("jobs") {
+            std::result::Result::Ok(stats) => stats.get("current-jobs-ready").cloned(),
+            Err(_) => None,
+        };
+
+        Ok(ready.is_some_and(|count| count != "0"))
+    }
+
+    fn request_steal(&self, queue: &mut Self::GlobalQueueLocation) -> Result<(), anyhow::Error> {
+        match queue {
+            GlobalQueue::Beanstalk(queue) => {
+                queue.use_tube("steals")?;
+                queue.put(b"steal", 0, Duration::from_secs(0), Duration::from_secs(10))?;
+            }
+            GlobalQueue::Amqp(queue) => {
+                queue.put("steals", b"steal", 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn take_steal_request(
+        &self,
+        queue: &mut Self::GlobalQueueLocation,
+    ) -> Result<bool, anyhow::Error> {
+        let queue = match queue {
+            GlobalQueue::Beanstalk(queue) => queue,
+            GlobalQueue::Amqp(queue) => {
+                let Some((request_id, _)) = queue.try_reserve("steals")? else {
+                    return Ok(false);
+                };
+                queue.ack(request_id)?;
+                return Ok(true);
+            }
+        };
+        queue.use_tube("steals")?;
+        let Some(request_id) = queue.peek_ready().ok().map(|request| request.id()) else {
+            return Ok(false);
+        };
+
+        // another busy worker may have claimed it first
+        Ok(queue.delete(request_id).is_ok())
+    }
+
+    fn steal_interval(&self) -> Option<usize> {
+        self.steal_interval
+    }
+
+    fn checkpoint_interval(&self) -> Option<usize> {
+        self.checkpoint_interval
+    }
+
+    fn write_checkpoint(
+        &self,
+        receipt: &Self::JobReceipt,
+        checkpoint: &RegistryCheckpoint<Self>,
+    ) -> Result<(), anyhow::Error> {
+        self.store
+            .write(&self.store.path(format!("checkpoint{receipt}")), checkpoint)
+    }
+
+    fn read_checkpoint(
+        &self,
+        receipt: &Self::JobReceipt,
+    ) -> Result<Option<RegistryCheckpoint<Self>>, anyhow::Error> {
+        self.store.read_optional(
+            "checkpoint",
+            &self.store.path(format!("checkpoint{receipt}")),
+        )
+    }
+
+    fn delete_checkpoint(&self, receipt: &Self::JobReceipt) -> Result<(), anyhow::Error> {
+        self.store
+            .remove(&self.store.path(format!("checkpoint{receipt}")))?;
+        Ok(())
+    }
+
+    fn is_published(&self, data: &Self::Data) -> Result<bool, anyhow::Error> {
+        self.seen.borrow_mut().contains(content_id(data))
+    }
+
+    fn mark_published(&self, data: &Self::Data) -> Result<(), anyhow::Error> {
+        self.seen.borrow_mut().insert(content_id(data))?;
+        Ok(())
+    }
+
+    fn base_db(&self) -> Result<Option<BaseSnapshot<Self::Location>>, anyhow::Error> {
+        self.store.read_optional("base", &self.store.path("base"))
+    }
+
+    fn set_base_db(&self, base: &BaseSnapshot<Self::Location>) -> Result<(), anyhow::Error> {
+        self.store.write(&self.store.path("base"), base)
+    }
+
+    fn layer_db(
+        &self,
+        base: Self::Database,
+        delta: Self::Database,
+    ) -> Result<Self::Database, anyhow::Error> {
+        Ok(Overlay::on(Arc::new(base.into_delta()), delta.into_delta()))
+    }
+
+    fn latest_db(&self) -> Result<Option<Self::Location>, anyhow::Error> {
+        self.store
+            .read_optional("latest", &self.store.path("latest"))
+    }
+
+    fn set_latest_db(&self, loc: &Self::Location) -> Result<(), anyhow::Error> {
+        self.store.write(&self.store.path("latest"), loc)
+    }
+
+    fn data_id(&self, data: &Self::Data) -> u64 {
+        match data {
+            Data::Node(node) => content_id(node),
+            Data::Edge(edge) => content_id(edge),
+            Data::InputFile(input_file) => content_id(input_file),
+            Data::GraphPath(path) => content_id(path),
         }
     }
+
+    fn data_route(&self, name: &str, id: u64) -> Option<Self::DataRoute> {
+        let data_type = match name {
+            "Node" => "nodes",
+            "Edge" => "edges",
+            "InputFile" => "input_files",
+            "GraphPath" => "paths",
+            _ => return None,
+        };
+        Some(DatabaseLocation {
+            data_type: data_type.to_string(),
+            hash: id,
+        })
+    }
+
+    fn write_provenance(
+        &self,
+        db_loc: &Self::Location,
+        log: &ProvenanceLog,
+    ) -> Result<(), anyhow::Error> {
+        self.store
+            .write(&self.store.sidecar(db_loc, "provenance"), log)
+    }
+
+    fn read_provenance(&self, db_loc: &Self::Location) -> Result<ProvenanceLog, anyhow::Error> {
+        Ok(self
+            .store
+            .read_optional("provenance", &self.store.sidecar(db_loc, "provenance"))?
+            .unwrap_or_default())
+    }
+
+    fn delete_provenance(&self, db_loc: &Self::Location) -> Result<(), anyhow::Error> {
+        self.store
+            .remove(&self.store.sidecar(db_loc, "provenance"))?;
+        Ok(())
+    }
+
+    fn db_routes(&self, db: &Self::Database) -> Vec<Self::DataRoute> {
+        let route = |data_type: &str, hash: &u64| DatabaseLocation {
+            data_type: data_type.to_string(),
+            hash: *hash,
+        };
+
+        db.iter(|t| &t.nodes)
+            .map(|(hash, _)| route("nodes", hash))
+            .chain(db.iter(|t| &t.edges).map(|(hash, _)| route("edges", hash)))
+            .chain(
+                db.iter(|t| &t.input_files)
+                    .map(|(hash, _)| route("input_files", hash)),
+            )
+            .chain(db.iter(|t| &t.paths).map(|(hash, _)| route("paths", hash)))
+            .collect()
+    }
+
+    fn cycles(&self) -> Vec<RegistryCycle<Self>> {
+        vec![
+            Box::new(Typed(Node::default())),
+            Box::new(Typed(Edge::default())),
+            Box::new(Typed(InputFile::default())),
+            Box::new(Typed(GraphPath::default())),
+        ]
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use silkworm::{export_csv, verify_fixtures, Codec, Compression, CycleGraph};
+    use std::fs;
+
+    fn holder() -> Holder {
+        Holder {
+            frontier: FrontierKind::default(),
+            budget: Budget::unlimited(),
+            steal_interval: None,
+            checkpoint_interval: None,
+            seen: RefCell::new(Box::new(MemorySeenSet::new(SeenMode::Exact))),
+            store: Rc::new(
+                FsStore::new(std::env::temp_dir().join("silkworm-graph-walk"))
+                    .unwrap()
+                    .with_header(Header::new(Codec::Bincode, SCHEMA_VERSION))
+                    .with_migrations(migrations())
+                    .with_columns(sqlite_columns()),
+            ),
+            backend: DatabaseBackend::Shard,
+            amqp_url: None,
+        }
+    }
+
+    fn path(labels: &[&str]) -> GraphPath {
+        let node = |label: &str| Node {
+            label: label.to_string(),
+        };
+        GraphPath {
+            edges: labels
+                .windows(2)
+                .map(|pair| Edge {
+                    from: node(pair[0]),
+                    to: node(pair[1]),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn it_has_a_consistent_cycle_graph() {
+        let graph = CycleGraph::from_registry(&holder());
+
+        assert!(graph.validate().is_ok());
+    }
+
+    #[test]
+    fn it_exports_edges_with_flattened_columns() {
+        let mut db = GraphDb::default();
+        insert(
+            &mut db,
+            |t| &t.edges,
+            |t| &mut t.edges,
+            "edges",
+            &path(&["a", "b"]).edges[0],
+        );
+
+        let mut csv = vec![];
+        export_csv(&db, "edges", &mut csv).unwrap();
+
+        let id = content_id(&path(&["a", "b"]).edges[0]);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!("id,from.label,to.label\n{id},a,b\n")
+        );
+    }
+
+    #[test]
+    fn it_queries_paths_between_two_nodes() {
+        let mut db = GraphDb::default();
+        for labels in [&["a", "c", "b"][..], &["a", "b"], &["b", "c"], &["a", "c"]] {
+            insert(
+                &mut db,
+                |t| &t.paths,
+                |t| &mut t.paths,
+                "paths",
+                &path(labels),
+            );
+        }
+        let results = ResultReader::new(holder(), db);
+
+        let found = paths_between(&results, "a", "b");
+
+        assert_eq!(
+            found.iter().map(path_labels).collect_vec(),
+            vec![vec!["a", "b"], vec!["a", "c", "b"]]
+        );
+        let id = content_id(&path(&["b", "c"]));
+        assert!(results.get::<GraphPath>(id) == Some(path(&["b", "c"])));
+        // looked up by key, so a path that isn't stored isn't found, nor is a node with its id
+        assert!(results
+            .get::<GraphPath>(content_id(&path(&["c", "b"])))
+            .is_none());
+        assert!(results.get::<Node>(id).is_none());
+    }
+
+    #[test]
+    fn it_finds_damaged_shards_in_the_store() {
+        let root = std::env::temp_dir().join(format!("silkworm-verify-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let holder = Holder {
+            store: Rc::new(
+                FsStore::new(&root)
+                    .unwrap()
+                    .with_header(Header::new(Codec::Bincode, SCHEMA_VERSION)),
+            ),
+            ..holder()
+        };
+        let mut db = GraphDb::default();
+        db.delta_mut().paths.insert(1, path(&["a", "b", "c"]));
+        let (whole, damaged) = (holder.store.path("whole"), holder.store.path("damaged"));
+        holder.write_db(&whole, &db).unwrap();
+        holder.write_db(&damaged, &db).unwrap();
+
+        let bytes = fs::read(&damaged).unwrap();
+        fs::write(&damaged, &bytes[..bytes.len() / 2]).unwrap();
+        let report = verify_store(&root).unwrap();
+
+        assert_eq!(report.checked.len(), 2);
+        assert!(report.is_damaged(&damaged) && !report.is_damaged(&whole));
+        assert!(holder.read_db(&damaged).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn it_reports_every_pending_merge_of_a_damaged_shard() {
+        let root = std::env::temp_dir().join(format!("silkworm-pending-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let holder = Holder {
+            store: Rc::new(
+                FsStore::new(&root)
+                    .unwrap()
+                    .with_header(Header::new(Codec::Bincode, SCHEMA_VERSION)),
+            ),
+            ..holder()
+        };
+        let mut db = GraphDb::default();
+        db.delta_mut().paths.insert(1, path(&["a", "b", "c"]));
+        let queue = holder.create_local_queue();
+
+        // three merge jobs announced in turn, the damaged shard behind the first
+        let announced = ["first", "second", "third"].map(|name| {
+            let (db_loc, queue_loc) = (
+                holder.store.location("database", "test", name),
+                holder.store.location("queue", "test", name),
+            );
+            holder.write_db(&db_loc, &db).unwrap();
+            holder.write_local_queue(&queue_loc, &queue).unwrap();
+            let record = merge_record(&holder.store, &db_loc).unwrap();
+            holder
+                .store
+                .write(&record, &(db_loc.clone(), queue_loc.clone()))
+                .unwrap();
+            (db_loc, queue_loc)
+        });
+        let damaged = &announced[1].0;
+        let bytes = fs::read(damaged).unwrap();
+        fs::write(damaged, &bytes[..bytes.len() / 2]).unwrap();
+
+        let report = verify_store(&root).unwrap();
+        let pending = pending_merges(&holder, &report).unwrap();
+        assert_eq!(pending.len(), 3);
+        let referencing = pending
+            .iter()
+            .filter(|(db, queue)| report.is_damaged(db) || report.is_damaged(queue))
+            .collect_vec();
+        assert_eq!(referencing, vec![&announced[1]]);
+        assert!(!verify(&holder).unwrap());
+
+        // a merge deletes the shards it read, and with them their records
+        holder.delete_db(announced[0].0.clone()).unwrap();
+        let pending = pending_merges(&holder, &verify_store(&root).unwrap()).unwrap();
+        assert_eq!(pending, announced[1..]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn it_streams_merges_like_it_collapses_in_memory() {
+        for backend in [
+            DatabaseBackend::Shard,
+            DatabaseBackend::Kv,
+            DatabaseBackend::Sqlite,
+        ] {
+            let holder = Holder {
+                backend,
+                ..holder()
+            };
+            streams_merges_like_it_collapses_in_memory(&holder, &holder);
+        }
+    }
+
+    #[test]
+    fn it_streams_merges_of_shards_another_host_wrote() {
+        let endpoint = fake_s3();
+        let bucket = || {
+            S3Store::new(&endpoint, "bucket")
+                .unwrap()
+                .with_credentials("access", "secret")
+        };
+        let cache = |host: &str| {
+            let root =
+                std::env::temp_dir().join(format!("silkworm-graph-walk-{host}-{}", process::id()));
+            let _ = fs::remove_dir_all(&root);
+            FsStore::new(root)
+                .unwrap()
+                .with_header(Header::new(Codec::Bincode, SCHEMA_VERSION))
+                .with_migrations(migrations())
+                .with_columns(sqlite_columns())
+                .with_s3(bucket())
+        };
+        for backend in [
+            DatabaseBackend::Shard,
+            DatabaseBackend::Kv,
+            DatabaseBackend::Sqlite,
+        ] {
+            let host = |store| Holder {
+                backend,
+                store: Rc::new(store),
+                ..holder()
+            };
+            let (merger, writer) = (host(cache("merger")), host(cache("writer")));
+            streams_merges_like_it_collapses_in_memory(&merger, &writer);
+            for host in [merger, writer] {
+                fs::remove_dir_all(host.store.root()).unwrap();
+            }
+        }
+    }
+
+    // a bucket kept in memory, answering unsigned as S3 answers the requests stores send
+    fn fake_s3() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            let mut objects = HashMap::new();
+            for mut request in server.incoming_requests() {
+                let key = request
+                    .url()
+                    .split('?')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let mut body = vec![];
+                request.as_reader().read_to_end(&mut body).unwrap();
+                let found = match request.method() {
+                    tiny_http::Method::Put => {
+                        objects.insert(key, body);
+                        Some(vec![])
+                    }
+                    tiny_http::Method::Get | tiny_http::Method::Head => objects.get(&key).cloned(),
+                    tiny_http::Method::Delete => objects.remove(&key).map(|_| vec![]),
+                    _ => None,
+                };
+                let response = match found {
+                    Some(body) => tiny_http::Response::from_data(body),
+                    None => tiny_http::Response::from_data(vec![]).with_status_code(404),
+                };
+                let _ = request.respond(response);
+            }
+        });
+        endpoint
+    }
+
+    // merges shards `writer` wrote with `holder`, whose store may cache them somewhere else
+    fn streams_merges_like_it_collapses_in_memory(holder: &Holder, writer: &Holder) {
+        let shard = |labels: &[&[&str]]| {
+            let mut db = GraphDb::default();
+            for labels in labels {
+                save_paths(&mut db, vec![&path(labels)]);
+                insert(
+                    &mut db,
+                    |t| &t.edges,
+                    |t| &mut t.edges,
+                    "edges",
+                    &path(labels).edges[0],
+                );
+            }
+            let loc = writer
+                .store
+                .location("database", "test", &writer.unique_string());
+            writer.write_db(&loc, &db).unwrap();
+            loc
+        };
+        let first = shard(&[&["a", "c", "b"], &["b", "c"]]);
+        let second = shard(&[&["a", "b"], &["b", "d", "c"], &["c", "d"]]);
+
+        let mut metrics = MergeMetrics::default();
+        let collapsed = writer.collapse_dbs(
+            writer.read_db(&first).unwrap(),
+            writer.read_db(&second).unwrap(),
+            &mut metrics,
+        );
+        // the same files, as the holder's store keeps them
+        let at = |loc: PathBuf| {
+            holder
+                .store
+                .path(loc.strip_prefix(writer.store.root()).unwrap())
+        };
+        let (first, second) = (at(first), at(second));
+        let mut streamed_metrics = MergeMetrics::default();
+        let out = holder
+            .store
+            .location("database", "test", &holder.unique_string());
+        assert!(holder
+            .collapse_db_files(&first, &second, &out, &mut streamed_metrics)
+            .unwrap());
+
+        let streamed = holder.read_db(&out).unwrap().into_delta();
+        assert!(streamed == collapsed.into_delta());
+        let shortest = |from: &str, to: &str| {
+            let node = |label: &str| Node {
+                label: label.to_string(),
+            };
+            &streamed.shortest[&ends_id(&node(from), &node(to))]
+        };
+        assert!(shortest("a", "b") == &path(&["a", "b"]));
+        assert!(shortest("b", "c") == &path(&["b", "c"]));
+        assert_eq!(streamed.paths.len(), 5);
+        assert_eq!(metrics.tables["shortest"].conflicts, 2);
+        assert_eq!(metrics.tables["paths"].conflicts, 0);
+        assert_eq!(streamed_metrics, metrics);
+        if holder.backend == DatabaseBackend::Sqlite {
+            let sql = holder.store.open_sqlite(&out).unwrap();
+            let direct: i64 = sql
+                .connection()
+                .query_row(
+                    "SELECT COUNT(*) FROM paths WHERE length = 1 AND source = 'b'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(direct, 1);
+        }
+        for loc in [first, second, out] {
+            holder.delete_db(loc).unwrap();
+        }
+    }
+
+    #[test]
+    fn it_persists_with_every_codec() {
+        for (codec, compression) in [
+            (Codec::Bincode, Compression::Zstd),
+            (Codec::Json, Compression::Lz4),
+            (Codec::MessagePack, Compression::None),
+            (Codec::Cbor, Compression::Zstd),
+        ] {
+            let holder = Holder {
+                store: Rc::new(
+                    FsStore::new(std::env::temp_dir().join(format!("silkworm-graph-{codec}")))
+                        .unwrap()
+                        .with_header(
+                            Header::new(codec, SCHEMA_VERSION).with_compression(compression),
+                        ),
+                ),
+                ..holder()
+            };
+            let mut db = GraphDb::default();
+            insert(
+                &mut db,
+                |t| &t.paths,
+                |t| &mut t.paths,
+                "paths",
+                &path(&["a", "b", "c"]),
+            );
+            let mut queue = holder.create_local_queue();
+            for route in holder.db_routes(&db) {
+                holder.produce_local(&mut queue, route, 1);
+            }
+
+            let db_loc = holder.db_location("test".to_string(), holder.unique_string());
+            let queue_loc = holder.queue_location("test".to_string(), holder.unique_string());
+            let (db_loc, queue_loc) = (db_loc.unwrap(), queue_loc.unwrap());
+            holder.write_db(&db_loc, &db).unwrap();
+            holder.write_local_queue(&queue_loc, &queue).unwrap();
+
+            assert!(Shard::open(&db_loc).unwrap().header() == holder.store.header());
+            assert!(holder.read_db(&db_loc).unwrap().into_delta() == db.into_delta());
+            let mut read = holder.read_queue(&queue_loc).unwrap();
+            assert!(holder.consume_local(&mut read) == holder.consume_local(&mut queue));
+
+            let message = holder.store.encode(&Data::GraphPath(path(&["a", "b"])));
+            let data: Data = holder.store.decode("job", &message.unwrap()).unwrap();
+            assert!(data == Data::GraphPath(path(&["a", "b"])));
+            fs::remove_dir_all(holder.store.root()).unwrap();
+        }
+    }
+
+    // each `fixtures/graph_walk/v<N>` holds databases, queues and checkpoints written under
+    // schema version N
+    #[test]
+    fn it_loads_fixtures_from_every_schema_version() {
+        let holder = holder();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/graph_walk");
+
+        for version in fs::read_dir(fixtures).unwrap() {
+            let loaded = verify_fixtures(&version.unwrap().path(), |path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                if name.starts_with("database") {
+                    assert!(!holder
+                        .read_db(&path.to_path_buf())?
+                        .delta()
+                        .paths
+                        .is_empty());
+                } else if name.starts_with("checkpoint") {
+                    let checkpoint: RegistryCheckpoint<Holder> =
+                        holder.store.read("checkpoint", path)?;
+                    assert!(!checkpoint.db.delta().paths.is_empty());
+                    assert!(holder.local_queue_len(&checkpoint.local_queue) > 0);
+                } else {
+                    assert!(holder.local_queue_len(&holder.read_queue(&path.to_path_buf())?) > 0);
+                }
+                Ok(())
+            });
+            assert_eq!(loaded.unwrap(), 6);
+        }
+    }
+
+    #[test]
+    fn it_adds_an_empty_shortest_table_to_v1_checkpoints() {
+        let holder = holder();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/fixtures/graph_walk");
+
+        let v1: RegistryCheckpoint<Holder> = holder
+            .store
+            .read("checkpoint", &fixtures.join("v1/checkpoint.bincode"))
+            .unwrap();
+        assert!(v1.db.delta().shortest.is_empty());
+        let v2: RegistryCheckpoint<Holder> = holder
+            .store
+            .read("checkpoint", &fixtures.join("v2/checkpoint.bincode"))
+            .unwrap();
+        assert!(v2.db.delta().paths == v1.db.delta().paths);
+        assert_eq!(v2.db.delta().shortest.len(), 1);
+        assert_eq!(holder.db_size(&v2.db), holder.db_size(&v1.db) + 1);
+    }
+
     #[test]
     fn it_tests_examples() {
         let result = 2 + 2;
@@ -0,0 +1,44 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Index};
+
+/// Derives `silkworm::Mergeable` for a struct by merging it field by field.
+#[proc_macro_derive(Mergeable)]
+pub fn derive_mergeable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let Data::Struct(data) = &input.data else {
+        return syn::Error::new_spanned(name, "Mergeable can only be derived for structs")
+            .to_compile_error()
+            .into();
+    };
+
+    let merges = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let field = &field.ident;
+                quote! { ::silkworm::Mergeable::merge(&mut self.#field, other.#field); }
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|index| {
+                let field = Index::from(index);
+                quote! { ::silkworm::Mergeable::merge(&mut self.#field, other.#field); }
+            })
+            .collect(),
+        Fields::Unit => vec![],
+    };
+
+    quote! {
+        impl #impl_generics ::silkworm::Mergeable for #name #ty_generics #where_clause {
+            fn merge(&mut self, other: Self) {
+                #(#merges)*
+            }
+        }
+    }
+    .into()
+}
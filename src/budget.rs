@@ -0,0 +1,112 @@
+use std::time::{Duration, Instant};
+
+/// Limits on how much local search a single global job may do before handing the rest back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Budget {
+    pub max_routes: Option<usize>,
+    pub max_duration: Option<Duration>,
+    /// In the units of `Registry::db_size`.
+    pub max_db_size: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exhausted {
+    Routes,
+    Duration,
+    DbSize,
+}
+
+impl Budget {
+    pub fn unlimited() -> Self {
+        Budget::default()
+    }
+
+    pub fn start(&self) -> BudgetMeter {
+        BudgetMeter {
+            budget: *self,
+            started: Instant::now(),
+            routes: 0,
+        }
+    }
+}
+
+/// Tracks one job's spend against its [`Budget`].
+#[derive(Clone, Debug)]
+pub struct BudgetMeter {
+    budget: Budget,
+    started: Instant,
+    routes: usize,
+}
+
+impl BudgetMeter {
+    pub fn record_route(&mut self) {
+        self.routes += 1;
+    }
+
+    pub fn routes(&self) -> usize {
+        self.routes
+    }
+
+    pub fn exhausted(&self, db_size: usize) -> Option<Exhausted> {
+        if self.budget.max_routes.is_some_and(|max| self.routes >= max) {
+            return Some(Exhausted::Routes);
+        }
+
+        if self
+            .budget
+            .max_duration
+            .is_some_and(|max| self.started.elapsed() >= max)
+        {
+            return Some(Exhausted::Duration);
+        }
+
+        if self.budget.max_db_size.is_some_and(|max| db_size >= max) {
+            return Some(Exhausted::DbSize);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_never_exhausts_without_limits() {
+        let mut meter = Budget::unlimited().start();
+        for _ in 0..1000 {
+            meter.record_route();
+        }
+
+        assert_eq!(meter.exhausted(usize::MAX), None);
+    }
+
+    #[test]
+    fn it_exhausts_each_limit() {
+        let mut meter = Budget {
+            max_routes: Some(2),
+            ..Budget::default()
+        }
+        .start();
+        meter.record_route();
+        assert_eq!(meter.exhausted(0), None);
+        meter.record_route();
+        assert_eq!(meter.exhausted(0), Some(Exhausted::Routes));
+
+        let meter = Budget {
+            max_duration: Some(Duration::ZERO),
+            ..Budget::default()
+        }
+        .start();
+        assert_eq!(meter.exhausted(0), Some(Exhausted::Duration));
+
+        let meter = Budget {
+            max_db_size: Some(10),
+            ..Budget::default()
+        }
+        .start();
+        assert_eq!(meter.exhausted(9), None);
+        assert_eq!(meter.exhausted(10), Some(Exhausted::DbSize));
+    }
+}
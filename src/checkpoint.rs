@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ProvenanceLog;
+
+/// The in-memory state of a global job part way through its local search.
+///
+/// Written every `Registry::checkpoint_interval` routes under the job's receipt, so a worker
+/// that reserves the same job after a crash picks up where the last one stopped.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint<Database, LocalQueue> {
+    pub db: Database,
+    pub local_queue: LocalQueue,
+    pub queue_to_write: LocalQueue,
+    #[serde(default)]
+    pub provenance: ProvenanceLog,
+}
@@ -0,0 +1,352 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::verify::{check, checksum};
+use crate::Corruption;
+
+/// How persisted files and queue messages encode their payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Codec {
+    #[default]
+    Bincode,
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Codec {
+    /// The id written into headers. These never change once released.
+    pub fn id(&self) -> u8 {
+        match self {
+            Codec::Bincode => 1,
+            Codec::Json => 2,
+            Codec::MessagePack => 3,
+            Codec::Cbor => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, anyhow::Error> {
+        Ok(match id {
+            1 => Codec::Bincode,
+            2 => Codec::Json,
+            3 => Codec::MessagePack,
+            4 => Codec::Cbor,
+            _ => bail!("unknown codec id {id}"),
+        })
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(match self {
+            Codec::Bincode => bincode::serialize(value)?,
+            Codec::Json => serde_json::to_vec(value)?,
+            Codec::MessagePack => rmp_serde::to_vec_named(value)?,
+            Codec::Cbor => {
+                let mut bytes = vec![];
+                ciborium::into_writer(value, &mut bytes)?;
+                bytes
+            }
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, anyhow::Error> {
+        Ok(match self {
+            Codec::Bincode => bincode::deserialize(bytes)?,
+            Codec::Json => serde_json::from_slice(bytes)?,
+            Codec::MessagePack => rmp_serde::from_slice(bytes)?,
+            Codec::Cbor => ciborium::from_reader(bytes)?,
+        })
+    }
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(Codec::Bincode),
+            "json" => Ok(Codec::Json),
+            "msgpack" => Ok(Codec::MessagePack),
+            "cbor" => Ok(Codec::Cbor),
+            _ => bail!("unknown codec {s}, expected bincode, json, msgpack or cbor"),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Codec::Bincode => "bincode",
+            Codec::Json => "json",
+            Codec::MessagePack => "msgpack",
+            Codec::Cbor => "cbor",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How encoded payloads are compressed. Shards compress each record on its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    const ZSTD_LEVEL: i32 = 3;
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Lz4 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, anyhow::Error> {
+        Ok(match id {
+            0 => Compression::None,
+            1 => Compression::Zstd,
+            2 => Compression::Lz4,
+            _ => bail!("unknown compression id {id}"),
+        })
+    }
+
+    pub fn compress<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, anyhow::Error> {
+        Ok(match self {
+            Compression::None => Cow::Borrowed(bytes),
+            Compression::Zstd => Cow::Owned(zstd::bulk::compress(bytes, Self::ZSTD_LEVEL)?),
+            Compression::Lz4 => Cow::Owned(lz4_flex::compress_prepend_size(bytes)),
+        })
+    }
+
+    pub fn decompress<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, anyhow::Error> {
+        Ok(match self {
+            Compression::None => Cow::Borrowed(bytes),
+            Compression::Zstd => Cow::Owned(zstd::decode_all(bytes)?),
+            Compression::Lz4 => Cow::Owned(lz4_flex::decompress_size_prepended(bytes)?),
+        })
+    }
+
+    /// The size of `bytes` once decompressed, read from the frame without decompressing it.
+    pub fn decompressed_len(&self, bytes: &[u8]) -> Option<usize> {
+        match self {
+            Compression::None => Some(bytes.len()),
+            Compression::Zstd => zstd::zstd_safe::get_frame_content_size(bytes)
+                .ok()
+                .flatten()
+                .map(|len| len as usize),
+            Compression::Lz4 => bytes
+                .get(..4)
+                .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => bail!("unknown compression {s}, expected none, zstd or lz4"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Prefixes every persisted file and message: `SILK`, the codec id and the schema version.
+///
+/// Readers take the codec from the header, so anything written by one codec can be read
+/// by a store configured with another. The compression id shares the codec's byte, in bits
+/// four to six, and its top bit says whether a crc32 checksum follows what the header prefixes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub codec: Codec,
+    pub schema: u32,
+    pub compression: Compression,
+    pub checksum: bool,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header::new(Codec::default(), 0)
+    }
+}
+
+impl Header {
+    pub const MAGIC: [u8; 4] = *b"SILK";
+    pub const LEN: usize = 9;
+
+    pub fn new(codec: Codec, schema: u32) -> Self {
+        Header {
+            codec,
+            schema,
+            compression: Compression::None,
+            checksum: true,
+        }
+    }
+
+    pub fn with_compression(self, compression: Compression) -> Self {
+        Header {
+            compression,
+            ..self
+        }
+    }
+
+    /// Bytes `frame` adds around the compressed payload.
+    pub(crate) fn framing_len(&self) -> usize {
+        Self::LEN + if self.checksum { 4 } else { 0 }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..4].copy_from_slice(&Self::MAGIC);
+        bytes[4] = self.codec.id() | self.compression.id() << 4 | u8::from(self.checksum) << 7;
+        bytes[5..].copy_from_slice(&self.schema.to_le_bytes());
+        bytes
+    }
+
+    /// Splits a header off the front of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<(Header, &[u8]), anyhow::Error> {
+        if !bytes.starts_with(&Self::MAGIC[..bytes.len().min(4)]) {
+            return Err(Corruption::Malformed("missing silkworm header".to_string()).into());
+        }
+        if bytes.len() < Self::LEN {
+            return Err(Corruption::Truncated.into());
+        }
+        let header = Header {
+            codec: Codec::from_id(bytes[4] & 0x0f)?,
+            schema: u32::from_le_bytes(bytes[5..Self::LEN].try_into()?),
+            compression: Compression::from_id(bytes[4] >> 4 & 0x07)?,
+            checksum: bytes[4] & 0x80 != 0,
+        };
+        Ok((header, &bytes[Self::LEN..]))
+    }
+
+    /// Splits a header off the front of `bytes`, checks the checksum if it has one and
+    /// decompresses the payload.
+    pub fn unframe(bytes: &[u8]) -> Result<(Header, Cow<'_, [u8]>), anyhow::Error> {
+        let (header, mut payload) = Self::parse(bytes)?;
+        if header.checksum {
+            let at = payload.len().checked_sub(4).ok_or(Corruption::Truncated)?;
+            let expected = u32::from_le_bytes(payload[at..].try_into()?);
+            check(expected, checksum(&[&bytes[..bytes.len() - 4]]))?;
+            payload = &payload[..at];
+        }
+        let payload = header
+            .compression
+            .decompress(payload)
+            .with_context(|| format!("decompressing {}", header.compression))?;
+        Ok((header, payload))
+    }
+
+    /// Compresses an encoded payload behind this header, followed by a checksum of both.
+    pub fn frame(&self, payload: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut bytes = self.to_bytes().to_vec();
+        bytes.extend_from_slice(&self.compression.compress(payload)?);
+        if self.checksum {
+            bytes.extend_from_slice(&checksum(&[&bytes]).to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        self.frame(&self.codec.encode(value)?)
+    }
+
+    /// Decodes a value with whichever codec and compression its header names.
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<(Header, T), anyhow::Error> {
+        let (header, payload) = Self::unframe(bytes)?;
+        let value = header
+            .codec
+            .decode(&payload)
+            .with_context(|| format!("decoding {}", header.codec))?;
+        Ok((header, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+    enum Value {
+        Unit,
+        Pair(u64, String),
+        Table { rows: HashMap<u64, Vec<u8>> },
+    }
+
+    #[test]
+    fn it_round_trips_through_every_codec() {
+        let values = vec![
+            Value::Unit,
+            Value::Pair(7, "seven".to_string()),
+            Value::Table {
+                rows: HashMap::from([(1, vec![1, 2]), (u64::MAX, vec![])]),
+            },
+        ];
+
+        for codec in [Codec::Bincode, Codec::Json, Codec::MessagePack, Codec::Cbor] {
+            for compression in [Compression::None, Compression::Zstd, Compression::Lz4] {
+                let written = Header::new(codec, 3).with_compression(compression);
+                let bytes = written.encode(&values).unwrap();
+                let (header, read) = Header::decode::<Vec<Value>>(&bytes).unwrap();
+
+                assert_eq!(&bytes[..4], b"SILK");
+                assert_eq!(header, written);
+                assert_eq!(read, values);
+                assert_eq!(
+                    compression.to_string().parse::<Compression>().unwrap(),
+                    compression
+                );
+            }
+            assert_eq!(codec.to_string().parse::<Codec>().unwrap(), codec);
+        }
+    }
+
+    #[test]
+    fn it_shrinks_repetitive_payloads() {
+        let repetitive = "node label ".repeat(100).into_bytes();
+
+        for compression in [Compression::Zstd, Compression::Lz4] {
+            let compressed = compression.compress(&repetitive).unwrap();
+
+            assert!(compressed.len() * 10 < repetitive.len());
+            assert_eq!(
+                compression.decompressed_len(&compressed),
+                Some(repetitive.len())
+            );
+            assert_eq!(compression.decompress(&compressed).unwrap(), repetitive);
+        }
+    }
+
+    #[test]
+    fn it_rejects_headerless_bytes() {
+        let bare = bincode::serialize(&1u32).unwrap();
+        assert!(Header::decode::<u32>(&bare).is_err());
+
+        let mut unknown = Header::default().encode(&1u32).unwrap();
+        unknown[4] = 99;
+        assert!(Header::decode::<u32>(&unknown).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::Overlay;
+
+/// The rows of one table, keyed by content id.
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<(u64, Value), anyhow::Error>> + 'a>;
+
+/// A database whose tables can be handed to tools outside Rust.
+pub trait Export {
+    /// Table names, in the order they are exported.
+    fn tables(&self) -> Vec<&'static str>;
+    fn rows(&self, table: &str) -> Rows<'_>;
+}
+
+/// A database holding a graph, so it can be drawn.
+pub trait GraphExport {
+    fn graph_nodes(&self) -> Box<dyn Iterator<Item = String> + '_>;
+    fn graph_edges(&self) -> Box<dyn Iterator<Item = (String, String)> + '_>;
+}
+
+/// Converts a table's entries to rows for [`Export::rows`].
+pub fn rows<'a, T: Serialize + 'a>(
+    entries: impl Iterator<Item = (&'a u64, &'a T)> + 'a,
+) -> Rows<'a> {
+    Box::new(entries.map(|(id, value)| Ok((*id, serde_json::to_value(value)?))))
+}
+
+impl<D: Export> Export for Overlay<D> {
+    fn tables(&self) -> Vec<&'static str> {
+        self.delta().tables()
+    }
+
+    fn rows(&self, table: &str) -> Rows<'_> {
+        Box::new(self.base().rows(table).chain(self.delta().rows(table)))
+    }
+}
+
+impl<D: GraphExport> GraphExport for Overlay<D> {
+    fn graph_nodes(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        Box::new(self.base().graph_nodes().chain(self.delta().graph_nodes()))
+    }
+
+    fn graph_edges(&self) -> Box<dyn Iterator<Item = (String, String)> + '_> {
+        Box::new(self.base().graph_edges().chain(self.delta().graph_edges()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::JsonLines => "jsonl",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => anyhow::bail!("unknown export format {s}, expected jsonl or csv"),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Writes one object per line, with the content id under `id`. Returns the rows written.
+pub fn export_jsonl(
+    db: &impl Export,
+    table: &str,
+    mut writer: impl Write,
+) -> Result<usize, anyhow::Error> {
+    let mut count = 0;
+    for row in db.rows(table) {
+        let (id, value) = row?;
+        let mut object = match value {
+            Value::Object(object) => object,
+            value => Map::from_iter([("value".to_string(), value)]),
+        };
+        object.insert("id".to_string(), id.into());
+
+        serde_json::to_writer(&mut writer, &object)?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Writes a header and one record per row, with nested fields flattened to `a.b` columns
+/// and lists written as JSON. The columns are every field of any row, in the order they first
+/// appear, so the rows are read twice. Fields a row lacks are left empty.
+pub fn export_csv(
+    db: &impl Export,
+    table: &str,
+    writer: impl Write,
+) -> Result<usize, anyhow::Error> {
+    let mut columns = vec!["id".to_string()];
+    for row in db.rows(table) {
+        let (_, value) = row?;
+        let mut fields = vec![];
+        flatten("", &value, &mut fields);
+        for (name, _) in fields {
+            if !columns.contains(&name) {
+                columns.push(name);
+            }
+        }
+    }
+
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(&columns)?;
+
+    let mut count = 0;
+    for row in db.rows(table) {
+        let (id, value) = row?;
+        let mut fields = vec![("id".to_string(), id.to_string())];
+        flatten("", &value, &mut fields);
+
+        csv.write_record(columns.iter().map(|column| {
+            fields
+                .iter()
+                .find(|(name, _)| name == column)
+                .map_or("", |(_, field)| field.as_str())
+        }))?;
+        count += 1;
+    }
+    csv.flush()?;
+    Ok(count)
+}
+
+fn flatten(prefix: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    let field = match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                let name = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(&name, value, fields);
+            }
+            return;
+        }
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    };
+
+    let name = if prefix.is_empty() { "value" } else { prefix };
+    fields.push((name.to_string(), field));
+}
+
+/// Writes every table to `<dir>/<table>.<extension>`.
+pub fn export_all(db: &impl Export, format: ExportFormat, dir: &Path) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(dir)?;
+    for table in db.tables() {
+        let file = File::create(dir.join(format!("{table}.{}", format.extension())))?;
+        match format {
+            ExportFormat::JsonLines => export_jsonl(db, table, BufWriter::new(file))?,
+            ExportFormat::Csv => export_csv(db, table, BufWriter::new(file))?,
+        };
+    }
+    Ok(())
+}
+
+/// Writes the graph in GraphViz DOT, drawing the edges along `highlight` in red.
+///
+/// Each highlighted path is a list of node names in order.
+pub fn export_dot(
+    graph: &impl GraphExport,
+    highlight: &[Vec<String>],
+    mut writer: impl Write,
+) -> Result<(), anyhow::Error> {
+    let highlighted: HashSet<(&str, &str)> = highlight
+        .iter()
+        .flat_map(|path| path.windows(2))
+        .map(|pair| (pair[0].as_str(), pair[1].as_str()))
+        .collect();
+
+    writeln!(writer, "digraph {{")?;
+    for node in graph.graph_nodes() {
+        writeln!(writer, "    {};", quote(&node))?;
+    }
+    for (from, to) in graph.graph_edges() {
+        let style = if highlighted.contains(&(from.as_str(), to.as_str())) {
+            " [color=red, penwidth=2]"
+        } else {
+            ""
+        };
+        writeln!(writer, "    {} -> {}{style};", quote(&from), quote(&to))?;
+    }
+    writeln!(writer, "}}")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Serialize)]
+    struct Link {
+        from: String,
+        to: String,
+    }
+
+    #[derive(Default)]
+    struct Db {
+        names: HashMap<u64, String>,
+        links: HashMap<u64, Link>,
+        notes: std::collections::BTreeMap<u64, Value>,
+    }
+
+    impl Export for Db {
+        fn tables(&self) -> Vec<&'static str> {
+            vec!["names", "links", "notes"]
+        }
+
+        fn rows(&self, table: &str) -> Rows<'_> {
+            match table {
+                "names" => rows(self.names.iter()),
+                "notes" => rows(self.notes.iter()),
+                _ => rows(self.links.iter()),
+            }
+        }
+    }
+
+    impl GraphExport for Db {
+        fn graph_nodes(&self) -> Box<dyn Iterator<Item = String> + '_> {
+            Box::new(self.names.values().cloned())
+        }
+
+        fn graph_edges(&self) -> Box<dyn Iterator<Item = (String, String)> + '_> {
+            Box::new(
+                self.links
+                    .values()
+                    .map(|link| (link.from.clone(), link.to.clone())),
+            )
+        }
+    }
+
+    fn db() -> Db {
+        let link = |from: &str, to: &str| Link {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        Db {
+            names: HashMap::from([(1, "a".to_string())]),
+            links: HashMap::from([(2, link("a", "b \"quoted\""))]),
+            ..Db::default()
+        }
+    }
+
+    fn written(write: impl FnOnce(&mut Vec<u8>)) -> String {
+        let mut buf = vec![];
+        write(&mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn it_exports_tables_as_json_lines_and_csv() {
+        let db = db();
+
+        let jsonl = written(|buf| assert_eq!(export_jsonl(&db, "names", buf).unwrap(), 1));
+        assert_eq!(jsonl, "{\"id\":1,\"value\":\"a\"}\n");
+
+        let csv = written(|buf| assert_eq!(export_csv(&db, "links", buf).unwrap(), 1));
+        assert_eq!(csv, "id,from,to\n2,a,\"b \"\"quoted\"\"\"\n");
+    }
+
+    #[test]
+    fn it_writes_every_column_of_any_row_to_csv() {
+        let mut db = db();
+        db.notes.insert(1, serde_json::json!({"text": "x"}));
+        db.notes
+            .insert(2, serde_json::json!({"by": {"name": "y"}, "text": "z"}));
+
+        let csv = written(|buf| assert_eq!(export_csv(&db, "notes", buf).unwrap(), 2));
+        assert_eq!(csv, "id,text,by.name\n1,x,\n2,z,y\n");
+
+        db.notes.clear();
+        let csv = written(|buf| assert_eq!(export_csv(&db, "notes", buf).unwrap(), 0));
+        assert_eq!(csv, "id\n");
+    }
+
+    #[test]
+    fn it_draws_highlighted_paths_in_dot() {
+        let highlight = vec![vec!["a".to_string(), "b \"quoted\"".to_string()]];
+
+        let dot = written(|buf| export_dot(&db(), &highlight, buf).unwrap());
+
+        assert_eq!(
+            dot,
+            "digraph {\n    \"a\";\n    \"a\" -> \"b \\\"quoted\\\"\" [color=red, penwidth=2];\n}\n"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// The order in which a job explores its local routes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum FrontierKind {
+    /// Depth first: the most recently produced route is explored next.
+    #[default]
+    Lifo,
+    /// Breadth first: routes are explored in the order they were produced.
+    Fifo,
+    /// Lowest cycle-supplied priority first, breadth first among equals.
+    Priority,
+    /// Uniformly random, reproducible for a given seed.
+    Random { seed: u64 },
+}
+
+impl FromStr for FrontierKind {
+    type Err = anyhow::Error;
+
+    /// Parses `lifo`, `fifo`, `priority` or `random:<seed>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("random", seed)) => Ok(FrontierKind::Random {
+                seed: seed.parse()?,
+            }),
+            None if s == "lifo" => Ok(FrontierKind::Lifo),
+            None if s == "fifo" => Ok(FrontierKind::Fifo),
+            None if s == "priority" => Ok(FrontierKind::Priority),
+            _ => anyhow::bail!(
+                "unknown frontier {s}, expected lifo, fifo, priority or random:<seed>"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for FrontierKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrontierKind::Lifo => write!(f, "lifo"),
+            FrontierKind::Fifo => write!(f, "fifo"),
+            FrontierKind::Priority => write!(f, "priority"),
+            FrontierKind::Random { seed } => write!(f, "random:{seed}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scored<R> {
+    priority: usize,
+    sequence: u64,
+    route: R,
+}
+
+impl<R> PartialEq for Scored<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<R> Eq for Scored<R> {}
+
+impl<R> PartialOrd for Scored<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R> Ord for Scored<R> {
+    // BinaryHeap pops the greatest, so lower priorities and earlier sequences compare greater
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+/// A library-provided `Registry::LocalQueue`.
+///
+/// It serializes with its strategy, so a queue written at the end of a job is read back
+/// in the same order by the merge.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LocalFrontier<R> {
+    Lifo(Vec<R>),
+    Fifo(VecDeque<R>),
+    Priority {
+        heap: BinaryHeap<Scored<R>>,
+        sequence: u64,
+    },
+    Random {
+        routes: Vec<R>,
+        state: u64,
+    },
+}
+
+impl<R> LocalFrontier<R> {
+    pub fn new(kind: FrontierKind) -> Self {
+        match kind {
+            FrontierKind::Lifo => LocalFrontier::Lifo(vec![]),
+            FrontierKind::Fifo => LocalFrontier::Fifo(VecDeque::new()),
+            FrontierKind::Priority => LocalFrontier::Priority {
+                heap: BinaryHeap::new(),
+                sequence: 0,
+            },
+            FrontierKind::Random { seed } => LocalFrontier::Random {
+                routes: vec![],
+                state: seed,
+            },
+        }
+    }
+
+    pub fn push(&mut self, route: R, priority: usize) {
+        match self {
+            LocalFrontier::Lifo(routes) => routes.push(route),
+            LocalFrontier::Fifo(routes) => routes.push_back(route),
+            LocalFrontier::Priority { heap, sequence } => {
+                heap.push(Scored {
+                    priority,
+                    sequence: *sequence,
+                    route,
+                });
+                *sequence += 1;
+            }
+            LocalFrontier::Random { routes, .. } => routes.push(route),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<R> {
+        match self {
+            LocalFrontier::Lifo(routes) => routes.pop(),
+            LocalFrontier::Fifo(routes) => routes.pop_front(),
+            LocalFrontier::Priority { heap, .. } => heap.pop().map(|scored| scored.route),
+            LocalFrontier::Random { routes, state } => {
+                if routes.is_empty() {
+                    return None;
+                }
+
+                let mut rng = StdRng::seed_from_u64(*state);
+                let index = rng.gen_range(0..routes.len());
+                *state = rng.gen();
+                Some(routes.swap_remove(index))
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            LocalFrontier::Lifo(routes) => routes.len(),
+            LocalFrontier::Fifo(routes) => routes.len(),
+            LocalFrontier::Priority { heap, .. } => heap.len(),
+            LocalFrontier::Random { routes, .. } => routes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(kind: FrontierKind, routes: &[(u32, usize)]) -> Vec<u32> {
+        let mut frontier = LocalFrontier::new(kind);
+        for (route, priority) in routes {
+            frontier.push(*route, *priority);
+        }
+        std::iter::from_fn(|| frontier.pop()).collect()
+    }
+
+    #[test]
+    fn it_orders_by_strategy() {
+        let routes = [(1, 2), (2, 0), (3, 2), (4, 1)];
+
+        assert_eq!(drain(FrontierKind::Lifo, &routes), vec![4, 3, 2, 1]);
+        assert_eq!(drain(FrontierKind::Fifo, &routes), vec![1, 2, 3, 4]);
+        assert_eq!(drain(FrontierKind::Priority, &routes), vec![2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn it_shuffles_reproducibly() {
+        let routes: Vec<(u32, usize)> = (0..20).map(|n| (n, 0)).collect();
+        let first = drain(FrontierKind::Random { seed: 7 }, &routes);
+
+        assert_eq!(first, drain(FrontierKind::Random { seed: 7 }, &routes));
+        assert_ne!(first, drain(FrontierKind::Fifo, &routes));
+
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_parses_kinds() {
+        for kind in [
+            FrontierKind::Lifo,
+            FrontierKind::Fifo,
+            FrontierKind::Priority,
+            FrontierKind::Random { seed: 3 },
+        ] {
+            assert_eq!(kind.to_string().parse::<FrontierKind>().unwrap(), kind);
+        }
+        assert!("depth".parse::<FrontierKind>().is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+
+use crate::Registry;
+
+/// The data types a cycle reads, befriends and produces, by variant name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleSignature {
+    pub input: &'static str,
+    pub friends: Vec<&'static str>,
+    pub outputs: Vec<&'static str>,
+    pub public: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphProblem {
+    /// A cycle produces a type that no cycle takes as input, so it can never be stored or searched.
+    UnhandledOutput {
+        cycle: &'static str,
+        output: &'static str,
+    },
+    /// A cycle befriends a type that no cycle stores, so its friends will always be empty.
+    UnstorableFriend {
+        cycle: &'static str,
+        friend: &'static str,
+    },
+    /// Several cycles take the same input, so routing to a cycle is ambiguous.
+    DuplicateInput { input: &'static str },
+}
+
+impl fmt::Display for GraphProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphProblem::UnhandledOutput { cycle, output } => {
+                write!(f, "{cycle} produces {output} but no cycle takes {output}")
+            }
+            GraphProblem::UnstorableFriend { cycle, friend } => {
+                write!(f, "{cycle} befriends {friend} but no cycle stores {friend}")
+            }
+            GraphProblem::DuplicateInput { input } => {
+                write!(f, "more than one cycle takes {input}")
+            }
+        }
+    }
+}
+
+/// The production graph declared by a registry's cycles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleGraph {
+    signatures: Vec<CycleSignature>,
+}
+
+impl CycleGraph {
+    pub fn new(signatures: Vec<CycleSignature>) -> Self {
+        CycleGraph { signatures }
+    }
+
+    pub fn from_registry<R: Registry>(reg: &R) -> Self {
+        let signatures = reg.cycles().iter().map(|cycle| cycle.signature()).collect();
+        CycleGraph { signatures }
+    }
+
+    pub fn signatures(&self) -> &[CycleSignature] {
+        &self.signatures
+    }
+
+    fn takes(&self, name: &str) -> bool {
+        self.signatures.iter().any(|sig| sig.input == name)
+    }
+
+    pub fn problems(&self) -> Vec<GraphProblem> {
+        let mut problems = vec![];
+
+        for (i, sig) in self.signatures.iter().enumerate() {
+            if self.signatures[..i]
+                .iter()
+                .any(|seen| seen.input == sig.input)
+            {
+                problems.push(GraphProblem::DuplicateInput { input: sig.input });
+            }
+
+            for output in &sig.outputs {
+                if !self.takes(output) {
+                    problems.push(GraphProblem::UnhandledOutput {
+                        cycle: sig.input,
+                        output,
+                    });
+                }
+            }
+
+            for friend in &sig.friends {
+                if !self.takes(friend) {
+                    problems.push(GraphProblem::UnstorableFriend {
+                        cycle: sig.input,
+                        friend,
+                    });
+                }
+            }
+        }
+
+        problems
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        let listed: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        anyhow::bail!("inconsistent cycle graph: {}", listed.join("; "))
+    }
+
+    /// Renders the graph as a mermaid state diagram, in the style of the README.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("stateDiagram-v2\n");
+
+        for sig in &self.signatures {
+            let verb = if sig.public { "publishes" } else { "produces" };
+            for output in &sig.outputs {
+                out.push_str(&format!("    {} --> {}: {}\n", sig.input, output, verb));
+            }
+            for friend in &sig.friends {
+                out.push_str(&format!("    {} --> {}: befriends\n", friend, sig.input));
+            }
+        }
+
+        out
+    }
+}
+
+/// Fails with every problem in the registry's cycle graph, if there are any.
+pub fn validate_registry<R: Registry>(reg: &R) -> Result<(), anyhow::Error> {
+    CycleGraph::from_registry(reg).validate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sig(
+        input: &'static str,
+        friends: Vec<&'static str>,
+        outputs: Vec<&'static str>,
+    ) -> CycleSignature {
+        CycleSignature {
+            input,
+            friends,
+            outputs,
+            public: false,
+        }
+    }
+
+    #[test]
+    fn it_accepts_a_closed_graph() {
+        let graph = CycleGraph::new(vec![
+            sig("Edge", vec!["Edge", "Path"], vec!["Path"]),
+            sig("Path", vec!["Edge", "Path"], vec!["Path"]),
+        ]);
+
+        assert!(graph.problems().is_empty());
+        assert!(graph.validate().is_ok());
+    }
+
+    #[test]
+    fn it_reports_every_problem() {
+        let graph = CycleGraph::new(vec![
+            sig("Edge", vec!["Node"], vec!["Path"]),
+            sig("Edge", vec![], vec![]),
+        ]);
+
+        assert_eq!(
+            graph.problems(),
+            vec![
+                GraphProblem::UnhandledOutput {
+                    cycle: "Edge",
+                    output: "Path"
+                },
+                GraphProblem::UnstorableFriend {
+                    cycle: "Edge",
+                    friend: "Node"
+                },
+                GraphProblem::DuplicateInput { input: "Edge" },
+            ]
+        );
+        assert!(graph.validate().is_err());
+    }
+
+    #[test]
+    fn it_renders_mermaid() {
+        let mut file = sig("File", vec![], vec!["Edge"]);
+        file.public = true;
+        let graph = CycleGraph::new(vec![file, sig("Edge", vec!["Edge"], vec![])]);
+
+        assert_eq!(
+            graph.to_mermaid(),
+            "stateDiagram-v2\n    File --> Edge: publishes\n    Edge --> Edge: befriends\n"
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use crate::{publish_base, publish_global, BaseSnapshot, Registry};
+
+/// Extends a finished search with new inputs instead of starting over.
+///
+/// Publishes the converged database at `final_db` as the base every new job starts from,
+/// then publishes `inputs`. Jobs only route data missing from the base, so the work done
+/// is proportional to the change. Their shards merge back with the final database as usual.
+pub fn start_increment<R: Registry>(
+    reg: &R,
+    final_db: &R::Location,
+    inputs: Vec<R::Data>,
+) -> Result<BaseSnapshot<R::Location>, anyhow::Error> {
+    let base = publish_base(reg, final_db)?;
+
+    let mut global_queue = reg.create_global_queue()?;
+    for input in inputs {
+        publish_global(reg, input, &mut global_queue, 0)?;
+    }
+
+    Ok(base)
+}
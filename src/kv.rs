@@ -0,0 +1,351 @@
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{ensure, Context};
+use redb::{
+    Database, ReadableTable, ReadableTableMetadata, TableDefinition, TableError, TableHandle,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::shard::{decode_record, encode_record};
+use crate::{Header, MergeMetrics, Migrations, ShardPolicies, StorageMetrics};
+
+/// Holds the header the store was created with, apart from the tables of records.
+const META: TableDefinition<&str, &[u8]> = TableDefinition::new("silkworm");
+
+fn table(name: &str) -> TableDefinition<'_, u64, &'static [u8]> {
+    TableDefinition::new(name)
+}
+
+/// A database kept in an embedded key-value store, one keyspace per table.
+///
+/// Unlike a shard, records can be put into an existing store, so a merge can ingest one store
+/// into the other in place instead of rewriting both. Values are encoded and
+/// compressed one at a time, as in shards.
+pub struct DatabaseStore {
+    db: Database,
+    loc: PathBuf,
+    header: Header,
+    migrations: Option<(Rc<Migrations>, u32)>,
+    storage: Cell<StorageMetrics>,
+}
+
+impl DatabaseStore {
+    /// Creates an empty store at `loc`, which must not exist yet.
+    pub fn create(loc: impl Into<PathBuf>, header: Header) -> Result<Self, anyhow::Error> {
+        let loc = loc.into();
+        ensure!(!loc.exists(), "{} already exists", loc.display());
+        let db = Database::create(&loc).with_context(|| format!("creating {}", loc.display()))?;
+
+        let txn = db.begin_write()?;
+        txn.open_table(META)?
+            .insert("header", &header.to_bytes()[..])?;
+        txn.commit()?;
+        Ok(Self::with_db(db, loc, header))
+    }
+
+    pub fn open(loc: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let loc = loc.into();
+        let db = Database::open(&loc).with_context(|| format!("opening {}", loc.display()))?;
+
+        let txn = db.begin_read()?;
+        let meta = txn.open_table(META).context("not a database store")?;
+        let header = meta.get("header")?.context("the store has no header")?;
+        let (header, _) = Header::parse(header.value())?;
+        drop((meta, txn));
+        Ok(Self::with_db(db, loc, header))
+    }
+
+    fn with_db(db: Database, loc: PathBuf, header: Header) -> Self {
+        DatabaseStore {
+            db,
+            loc,
+            header,
+            migrations: None,
+            storage: Cell::default(),
+        }
+    }
+
+    /// Upgrades values to schema version `schema` as they are decoded, each table as its own kind.
+    pub fn migrated(self, migrations: Rc<Migrations>, schema: u32) -> Self {
+        DatabaseStore {
+            migrations: Some((migrations, schema)),
+            ..self
+        }
+    }
+
+    pub fn loc(&self) -> &Path {
+        &self.loc
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Bytes of values put so far, before and after compression.
+    pub fn storage_metrics(&self) -> StorageMetrics {
+        self.storage.get()
+    }
+
+    fn record(&self, value: &[u8]) {
+        let mut storage = self.storage.get();
+        storage += StorageMetrics {
+            raw: self
+                .header
+                .compression
+                .decompressed_len(value)
+                .unwrap_or(value.len()) as u64,
+            stored: value.len() as u64,
+        };
+        self.storage.set(storage);
+    }
+
+    fn decode<V: DeserializeOwned>(&self, table: &str, value: &[u8]) -> Result<V, anyhow::Error> {
+        match &self.migrations {
+            Some((migrations, schema)) => {
+                let value = self.header.compression.decompress(value)?;
+                migrations.decode(table, self.header, *schema, &value)
+            }
+            None => decode_record(self.header, value),
+        }
+    }
+
+    pub fn tables(&self) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self
+            .db
+            .begin_read()?
+            .list_tables()?
+            .map(|table| table.name().to_string())
+            .filter(|name| name != META.name())
+            .collect())
+    }
+
+    pub fn len(&self, name: &str) -> Result<usize, anyhow::Error> {
+        match self.db.begin_read()?.open_table(table(name)) {
+            Ok(table) => Ok(table.len()? as usize),
+            Err(TableError::TableDoesNotExist(_)) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn is_empty(&self, name: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.len(name)? == 0)
+    }
+
+    /// Puts one value, replacing whatever the key held.
+    pub fn put<V: Serialize>(&self, name: &str, key: u64, value: &V) -> Result<(), anyhow::Error> {
+        self.write_table(name, [(&key, value)])
+    }
+
+    /// Puts many values into a table in one transaction.
+    pub fn write_table<'a, V: Serialize + 'a>(
+        &self,
+        name: &str,
+        records: impl IntoIterator<Item = (&'a u64, &'a V)>,
+    ) -> Result<(), anyhow::Error> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(table(name))?;
+            for (key, value) in records {
+                let value = encode_record(self.header, value)?;
+                self.record(&value);
+                table.insert(key, &value[..])?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_value<V: DeserializeOwned>(
+        &self,
+        name: &str,
+        key: u64,
+    ) -> Result<Option<V>, anyhow::Error> {
+        let table = match self.db.begin_read()?.open_table(table(name)) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let value = table.get(key)?;
+        value
+            .map(|value| self.decode(name, value.value()))
+            .transpose()
+    }
+
+    /// Every value of a table in key order, empty if the store has no such table. Values are
+    /// decoded as they are reached, from the store as it was when this was called.
+    pub fn values<'a, V: DeserializeOwned>(
+        &'a self,
+        name: &'a str,
+    ) -> Result<impl Iterator<Item = Result<(u64, V), anyhow::Error>> + 'a, anyhow::Error> {
+        let range = match self.db.begin_read()?.open_table(table(name)) {
+            Ok(table) => Some(table.range(0..)?),
+            Err(TableError::TableDoesNotExist(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(range.into_iter().flatten().map(move |record| {
+            let (key, value) = record?;
+            Ok((key.value(), self.decode(name, value.value())?))
+        }))
+    }
+
+    /// Puts every record of `other` into this store in one transaction, recording what policies
+    /// did in `metrics`.
+    ///
+    /// Keys both stores hold are resolved as `merge_shards` resolves them, with this store as
+    /// the first. Records are copied without decoding them, so both must share a header.
+    pub fn ingest(
+        &self,
+        other: &DatabaseStore,
+        policies: &ShardPolicies,
+        metrics: &mut MergeMetrics,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            self.header == other.header,
+            "can't ingest a store written as {:?} into {:?}",
+            other.header,
+            self.header
+        );
+        for name in policies.tables() {
+            metrics.tables.entry(name).or_default();
+        }
+
+        let txn = self.db.begin_write()?;
+        let read = other.db.begin_read()?;
+        for name in other.tables()? {
+            let mut into = txn.open_table(table(&name))?;
+            let policy = policies.resolver(&name);
+
+            for record in read.open_table(table(&name))?.iter()? {
+                let (key, other) = record?;
+                let (key, other) = (key.value(), other.value());
+                let value = into.get(key)?.map(|value| value.value().to_vec());
+
+                let put = match (value, policy) {
+                    (Some(value), Some((name, resolve))) => {
+                        let table = metrics.tables.entry(name).or_default();
+                        table.merged += 1;
+                        table.shared += 1;
+                        if value != other {
+                            table.conflicts += 1;
+                        }
+                        resolve(self.header, &value, other)?
+                    }
+                    (None, Some((name, _))) => {
+                        metrics.tables.entry(name).or_default().merged += 1;
+                        other.to_vec()
+                    }
+                    (_, None) => other.to_vec(),
+                };
+                self.record(&put);
+                into.insert(key, &put[..])?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, Compression, Policy, TableMetrics};
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("silkworm-kv-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn it_puts_and_reads_tables() {
+        let dir = dir("read");
+        let header = Header::new(Codec::Json, 1).with_compression(Compression::Zstd);
+        let store = DatabaseStore::create(dir.join("db"), header).unwrap();
+        let squares = (0..100u64)
+            .rev()
+            .map(|n| (n, n * n))
+            .collect::<HashMap<_, _>>();
+        store.write_table("squares", &squares).unwrap();
+        store.put("squares", 7, &0u64).unwrap();
+        store.write_table::<u64>("empty", []).unwrap();
+        drop(store);
+
+        let store = DatabaseStore::open(dir.join("db")).unwrap();
+        assert_eq!(store.header(), header);
+        let mut tables = store.tables().unwrap();
+        tables.sort();
+        assert_eq!(tables, vec!["empty", "squares"]);
+        assert_eq!(store.len("squares").unwrap(), 100);
+        assert!(store.is_empty("empty").unwrap() && store.is_empty("missing").unwrap());
+        assert_eq!(store.get_value::<u64>("squares", 9).unwrap(), Some(81));
+        assert_eq!(store.get_value::<u64>("squares", 7).unwrap(), Some(0));
+        assert_eq!(store.get_value::<u64>("missing", 7).unwrap(), None);
+
+        let values: Vec<(u64, u64)> = store
+            .values("squares")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(values.len(), 100);
+        assert!(values.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(DatabaseStore::create(dir.join("db"), header).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_ingests_one_store_into_another() {
+        let dir = dir("ingest");
+        let header = Header::new(Codec::Bincode, 1);
+        let first = DatabaseStore::create(dir.join("first"), header).unwrap();
+        first
+            .write_table("counts", &HashMap::from([(1, 2u32), (3, 1)]))
+            .unwrap();
+        first
+            .write_table("rest", &HashMap::from([(1, 1u32)]))
+            .unwrap();
+        let second = DatabaseStore::create(dir.join("second"), header).unwrap();
+        second
+            .write_table("counts", &HashMap::from([(1, 3u32), (2, 1), (3, 1)]))
+            .unwrap();
+        second
+            .write_table("rest", &HashMap::from([(1, 2u32)]))
+            .unwrap();
+        second
+            .write_table("new", &HashMap::from([(5, 5u32)]))
+            .unwrap();
+
+        let policies = ShardPolicies::new().table("counts", Policy::<u32>::sum());
+        let mut metrics = MergeMetrics::default();
+        first.ingest(&second, &policies, &mut metrics).unwrap();
+
+        assert_eq!(
+            first
+                .values::<u32>("counts")
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![(1, 5), (2, 1), (3, 2)]
+        );
+        assert_eq!(first.get_value::<u32>("rest", 1).unwrap(), Some(2));
+        assert_eq!(first.get_value::<u32>("new", 5).unwrap(), Some(5));
+        assert_eq!(
+            metrics.tables["counts"],
+            TableMetrics {
+                merged: 3,
+                shared: 2,
+                conflicts: 1
+            }
+        );
+        assert!(!metrics.tables.contains_key("rest"));
+
+        let json = DatabaseStore::create(dir.join("json"), Header::new(Codec::Json, 1)).unwrap();
+        assert!(json.ingest(&second, &policies, &mut metrics).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,86 @@
 use anyhow::{Context, Ok};
 
+// lets `#[derive(Mergeable)]` name this crate from inside it
+extern crate self as silkworm;
+
+mod amqp;
+mod budget;
+mod checkpoint;
+mod codec;
+mod export;
+mod frontier;
+mod graph;
+mod incremental;
+mod kv;
+mod merge;
+mod mergeable;
+mod migrate;
+mod object;
+mod overlay;
+mod policy;
+mod provenance;
+mod results;
+mod seen;
+mod shard;
+mod sqlite;
+mod store;
+mod typed;
+mod verify;
+
+pub use amqp::AmqpQueue;
+pub use budget::{Budget, BudgetMeter, Exhausted};
+pub use checkpoint::Checkpoint;
+pub use codec::{Codec, Compression, Header};
+pub use export::{
+    export_all, export_csv, export_dot, export_jsonl, rows, Export, ExportFormat, GraphExport, Rows,
+};
+pub use frontier::{FrontierKind, LocalFrontier};
+pub use graph::{validate_registry, CycleGraph, CycleSignature, GraphProblem};
+pub use incremental::start_increment;
+pub use kv::DatabaseStore;
+pub use merge::{DatabaseLookup, Inspection, MergeClass, MergeClassifier, RouteLookup};
+pub use mergeable::Mergeable;
+pub use migrate::{verify_fixtures, Migrations};
+pub use object::S3Store;
+pub use overlay::{publish_base, BaseSnapshot, Overlay};
+pub use policy::{CollapsePolicies, MergeMetrics, Policy, TableMetrics};
+pub use provenance::{Provenance, ProvenanceLog};
+pub use results::ResultReader;
+pub use seen::{content_id, BloomFilter, FileSeenSet, MemorySeenSet, SeenMode, SeenSet};
+pub use shard::{merge_shards, Records, Shard, ShardPolicies, ShardWriter};
+pub use silkworm_derive::Mergeable;
+pub use sqlite::{SqliteColumns, SqliteStore};
+pub use store::{FsStore, StorageMetrics};
+pub use typed::{Typed, TypedCycle, Variant};
+pub use verify::{verify_store, Corruption, StoreReport};
+
+/// Two merge-ready shards, each with the receipt of the merge job that announced it.
+pub struct MergeEvent<Location, JobReceipt> {
+    pub first_db: Location,
+    pub first_queue: Location,
+    pub first_receipt: JobReceipt,
+    pub second_db: Location,
+    pub second_queue: Location,
+    pub second_receipt: JobReceipt,
+}
+
+pub type RegistryMergeEvent<R> = MergeEvent<<R as Registry>::Location, <R as Registry>::JobReceipt>;
+
+pub type RegistryCheckpoint<R> = Checkpoint<<R as Registry>::Database, <R as Registry>::LocalQueue>;
+
+pub type RegistryCycle<R> = Box<
+    dyn DataCycle<
+        Database = <R as Registry>::Database,
+        DataRoute = <R as Registry>::DataRoute,
+        Data = <R as Registry>::Data,
+    >,
+>;
+
+/// Where a worker finds its jobs, databases and queues, and which cycles it runs.
+///
+/// Optional features (budgets, stealing, checkpoints, deduplication, increments, streamed merges
+/// and provenance) have default methods that leave them off, so a registry only implements the
+/// ones it uses.
 pub trait Registry {
     type Database;
     type Location;
@@ -7,7 +88,7 @@ pub trait Registry {
     type DataRoute: Clone;
     type JobReceipt;
     type LocalQueue: Clone;
-    type Data;
+    type Data: Clone;
 
     fn unique_string(&self) -> String;
     fn worker_name(&self) -> String;
@@ -39,7 +120,7 @@ pub trait Registry {
     ) -> Result<(), anyhow::Error>;
     fn create_local_queue(&self) -> Self::LocalQueue;
     fn consume_local(&self, queue: &mut Self::LocalQueue) -> Option<Self::DataRoute>;
-    fn produce_local(&self, queue: &mut Self::LocalQueue, loc: Self::DataRoute);
+    fn produce_local(&self, queue: &mut Self::LocalQueue, loc: Self::DataRoute, priority: usize);
     fn produce_merge_event(
         &self,
         queue: &mut Self::GlobalQueueLocation,
@@ -49,18 +130,38 @@ pub trait Registry {
     fn consume_merge_event(
         &self,
         queue: &mut Self::GlobalQueueLocation,
-    ) -> Result<
-        Option<(
-            Self::Location,
-            Self::Location,
-            Self::JobReceipt,
-            Self::Location,
-            Self::Location,
-            Self::JobReceipt,
-        )>,
-        anyhow::Error,
-    >;
-    fn collapse_dbs(&self, db: &Self::Database, other: &Self::Database) -> Self::Database;
+    ) -> Result<Option<RegistryMergeEvent<Self>>, anyhow::Error>;
+    /// Collapses two shards, recording what any `CollapsePolicies` did in `metrics`.
+    fn collapse_dbs(
+        &self,
+        db: Self::Database,
+        other: Self::Database,
+        metrics: &mut MergeMetrics,
+    ) -> Self::Database;
+    /// Streams two stored shards into `out` without reading them into memory, like `merge_shards`.
+    /// Returns false if the store can't, in which case merges use `collapse_dbs`.
+    ///
+    /// It may consume `first` by filling it in place, so `delete_db` must accept it being gone.
+    fn collapse_db_files(
+        &self,
+        _first: &Self::Location,
+        _second: &Self::Location,
+        _out: &Self::Location,
+        _metrics: &mut MergeMetrics,
+    ) -> Result<bool, anyhow::Error> {
+        Ok(false)
+    }
+    /// Looks routes up in a stored database without reading it, so merges classify their routes
+    /// against the stored shards. None, the default, has merges read the shards instead.
+    fn open_lookup(
+        &self,
+        _loc: &Self::Location,
+    ) -> Result<Option<Box<dyn RouteLookup<Self> + '_>>, anyhow::Error>
+    where
+        Self: Sized,
+    {
+        Ok(None)
+    }
     fn queue_location(
         &self,
         worker_name: String,
@@ -79,6 +180,113 @@ pub trait Registry {
         &self,
         data: &Self::Data,
     ) -> Box<dyn DataCycle<Database = Self::Database, DataRoute = Self::DataRoute, Data = Self::Data>>;
+    /// Every cycle the registry can hand out, used to validate the production graph.
+    fn cycles(&self) -> Vec<RegistryCycle<Self>>;
+    /// Limits on a job before it hands off its frontier. Unlimited unless overridden.
+    fn budget(&self) -> Budget {
+        Budget::unlimited()
+    }
+    /// The size a budget's growth limit measures, only asked for when the budget has one.
+    fn db_size(&self, _db: &Self::Database) -> usize {
+        0
+    }
+    fn local_queue_len(&self, queue: &Self::LocalQueue) -> usize;
+    fn has_global_work(
+        &self,
+        _queue: &mut Self::GlobalQueueLocation,
+    ) -> Result<bool, anyhow::Error> {
+        Ok(true)
+    }
+    /// Asks a busy worker to hand off part of its local frontier.
+    fn request_steal(&self, _queue: &mut Self::GlobalQueueLocation) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    /// Claims a pending steal request, if there is one.
+    fn take_steal_request(
+        &self,
+        _queue: &mut Self::GlobalQueueLocation,
+    ) -> Result<bool, anyhow::Error> {
+        Ok(false)
+    }
+    /// Routes processed between checks for steal requests. `None`, the default, disables stealing.
+    fn steal_interval(&self) -> Option<usize> {
+        None
+    }
+    /// Routes processed between checkpoints. `None`, the default, disables checkpointing.
+    fn checkpoint_interval(&self) -> Option<usize> {
+        None
+    }
+    /// Also written for each route a job hands off, so the new job searches it with what the
+    /// old one had found. Registries that don't keep checkpoints hand off the data alone.
+    fn write_checkpoint(
+        &self,
+        _receipt: &Self::JobReceipt,
+        _checkpoint: &RegistryCheckpoint<Self>,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    fn read_checkpoint(
+        &self,
+        _receipt: &Self::JobReceipt,
+    ) -> Result<Option<RegistryCheckpoint<Self>>, anyhow::Error> {
+        Ok(None)
+    }
+    fn delete_checkpoint(&self, _receipt: &Self::JobReceipt) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    /// Whether data was already published globally. By default nothing is recorded and
+    /// everything is published.
+    fn is_published(&self, _data: &Self::Data) -> Result<bool, anyhow::Error> {
+        Ok(false)
+    }
+    /// Records that data was published globally, once its job has been produced.
+    fn mark_published(&self, _data: &Self::Data) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    /// The latest published snapshot that jobs start from, if any. None by default.
+    fn base_db(&self) -> Result<Option<BaseSnapshot<Self::Location>>, anyhow::Error> {
+        Ok(None)
+    }
+    fn set_base_db(&self, _base: &BaseSnapshot<Self::Location>) -> Result<(), anyhow::Error> {
+        anyhow::bail!("this registry does not keep a base database")
+    }
+    /// Makes the base visible through a job's delta. Only the delta should be persisted by `write_db`.
+    ///
+    /// Only called once `base_db` returns a base, so registries that keep one must implement it.
+    fn layer_db(
+        &self,
+        _base: Self::Database,
+        _delta: Self::Database,
+    ) -> Result<Self::Database, anyhow::Error> {
+        anyhow::bail!("this registry keeps a base database but can't layer it")
+    }
+    /// The most recently written shard or merge result. Once the queues drain it is the final database.
+    fn latest_db(&self) -> Result<Option<Self::Location>, anyhow::Error>;
+    fn set_latest_db(&self, loc: &Self::Location) -> Result<(), anyhow::Error>;
+    /// Every route stored in a database, so its results can be read back.
+    fn db_routes(&self, db: &Self::Database) -> Vec<Self::DataRoute>;
+    /// A stable id for a datum: the `content_id` of the value it wraps, as `ResultReader::get` expects.
+    fn data_id(&self, data: &Self::Data) -> u64;
+    /// The route a datum of the `Data` variant `name` is stored at, for registries that key their
+    /// tables by `data_id`. None, the default, has `ResultReader::get` scan for the datum instead.
+    fn data_route(&self, _name: &str, _id: u64) -> Option<Self::DataRoute> {
+        None
+    }
+    /// Stores where each datum of a database came from. Not kept unless overridden.
+    fn write_provenance(
+        &self,
+        _db_loc: &Self::Location,
+        _log: &ProvenanceLog,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    /// The provenance stored alongside a database, empty if none was written.
+    fn read_provenance(&self, _db_loc: &Self::Location) -> Result<ProvenanceLog, anyhow::Error> {
+        Ok(ProvenanceLog::default())
+    }
+    fn delete_provenance(&self, _db_loc: &Self::Location) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
 }
 
 pub trait DataCycle {
@@ -90,146 +298,273 @@ pub trait DataCycle {
     fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Data>;
     fn stop_data(&self, data: &Self::Data, db: &Self::Database) -> bool;
     fn get_friends(&self, db: &Self::Database, route: &Self::DataRoute) -> Vec<Self::Data>;
-    fn stop_friends(&self, friends: &Vec<Self::Data>) -> bool;
-    fn search(&self, data: &Self::Data, friends: &Vec<Self::Data>) -> Vec<Self::Data>;
+    fn stop_friends(&self, friends: &[Self::Data]) -> bool;
+    fn search(&self, data: &Self::Data, friends: &[Self::Data]) -> Vec<Self::Data>;
+    fn store(&self, db: &mut Self::Database, data: &Self::Data) -> Option<Self::DataRoute>;
     fn save(
         &self,
         db: &mut Self::Database,
         new_data: Vec<&Self::Data>,
     ) -> Vec<Option<Self::DataRoute>>;
     fn public(&self) -> bool;
+    /// Priority of data this cycle produced, lowest first, on both the global and local queues.
+    fn priority(&self, data: &Self::Data) -> usize;
+    fn signature(&self) -> CycleSignature;
+    /// Whether the cycle can read this data as its input. A job whose data the registry routes
+    /// to a cycle that can't is left unacked rather than finished with nothing stored.
+    fn accepts(&self, _data: &Self::Data) -> bool {
+        true
+    }
 }
 
-pub fn run_worker(reg: impl Registry) -> Result<(), anyhow::Error> {
+/// Runs one job or merge, returning what the policies did when it was a merge.
+pub fn run_worker(reg: impl Registry) -> Result<Option<MergeMetrics>, anyhow::Error> {
+    validate_registry(&reg)?;
+
     let mut global_queue = reg.create_global_queue()?;
 
     if let Some(merge_event) = reg.consume_merge_event(&mut global_queue)? {
-        let (
-            first_db_loc,
-            first_queue_loc,
-            first_merge_rec,
-            second_db_loc,
-            second_queue_loc,
-            second_merge_rec,
-        ) = merge_event;
-
-        let first_db = reg.read_db(&first_db_loc)?;
-        let mut first_queue = reg.read_queue(&first_queue_loc)?;
+        let MergeEvent {
+            first_db: first_db_loc,
+            first_queue: first_queue_loc,
+            first_receipt: first_merge_rec,
+            second_db: second_db_loc,
+            second_queue: second_queue_loc,
+            second_receipt: second_merge_rec,
+        } = merge_event;
 
-        let second_db = reg.read_db(&second_db_loc)?;
+        let mut first_queue = reg.read_queue(&first_queue_loc)?;
         let mut second_queue = reg.read_queue(&second_queue_loc)?;
 
         let new_db_loc = reg.db_location(reg.worker_name(), reg.unique_string())?;
-        let new_db = reg.collapse_dbs(&first_db, &second_db);
+        let mut new_provenance = reg.read_provenance(&first_db_loc)?;
+        new_provenance.merge(reg.read_provenance(&second_db_loc)?);
 
         let new_queue_loc = reg.queue_location(reg.worker_name(), reg.unique_string())?;
         let mut new_queue = reg.create_local_queue();
 
-        while let Some(data_route) = reg.consume_local(&mut first_queue) {
-            let cycle = reg.get_data_cycle(data_route.clone());
-            let first_data_option = cycle.get_data(&first_db, &data_route);
-            let second_data_option = cycle.get_data(&second_db, &data_route);
-
-            if first_data_option.is_some() && second_data_option.is_some() {
-                reg.produce_local(&mut new_queue, data_route);
-                continue;
+        // each shard explored its routes without the other's data, so some are searched again.
+        // They are inspected before the collapse, which may consume the shards
+        let classifier = MergeClassifier::new(&reg, &new_provenance);
+        let has_routes = reg.local_queue_len(&first_queue) + reg.local_queue_len(&second_queue) > 0;
+        let mut loaded = None;
+        let mut inspected = vec![];
+        if has_routes {
+            let lookups = match (
+                reg.open_lookup(&first_db_loc)?,
+                reg.open_lookup(&second_db_loc)?,
+            ) {
+                (Some(first), Some(second)) => Some((first, second)),
+                _ => None,
+            };
+            if lookups.is_none() {
+                loaded = Some((reg.read_db(&first_db_loc)?, reg.read_db(&second_db_loc)?));
             }
+            let (first, second): (&dyn RouteLookup<_>, &dyn RouteLookup<_>) =
+                match (&lookups, &loaded) {
+                    (Some((first, second)), _) => (first.as_ref(), second.as_ref()),
+                    (None, Some((first, second))) => {
+                        (&classifier.loaded(first), &classifier.loaded(second))
+                    }
+                    (None, None) => unreachable!("shards without lookups are loaded"),
+                };
 
-            let first_friends = cycle.get_friends(&first_db, &data_route);
-            let second_friends = cycle.get_friends(&second_db, &data_route);
-
-            if !first_friends.is_empty() && !second_friends.is_empty() {
-                reg.produce_local(&mut new_queue, data_route);
-                continue;
+            for (own, other, queue) in [
+                (first, second, &mut first_queue),
+                (second, first, &mut second_queue),
+            ] {
+                while let Some(data_route) = reg.consume_local(queue) {
+                    let cycle = reg.get_data_cycle(data_route.clone());
+                    let inspection = classifier.inspect(&cycle, own, other, &data_route)?;
+                    inspected.push((data_route.clone(), inspection));
+                    reg.produce_local(&mut new_queue, data_route, 0);
+                }
             }
-
-            if (first_data_option.is_some()
-                && second_data_option.is_none()
-                && !first_friends.is_empty()
-                && second_friends.is_empty())
-                || (first_data_option.is_none()
-                    && second_data_option.is_some()
-                    && first_friends.is_empty()
-                    && !second_friends.is_empty())
-            {
-                reg.produce_local(&mut new_queue, data_route);
-                continue;
-            }
-
-            // todo play the whole data cycle on first_queue
-            reg.produce_local(&mut new_queue, data_route);
         }
 
-        while let Some(data_route) = reg.consume_local(&mut second_queue) {
-            let cycle = reg.get_data_cycle(data_route.clone());
-            let first_data_option = cycle.get_data(&first_db, &data_route);
-            let second_data_option = cycle.get_data(&second_db, &data_route);
-
-            if first_data_option.is_some() && second_data_option.is_some() {
-                reg.produce_local(&mut new_queue, data_route);
-                continue;
+        let mut metrics = MergeMetrics::default();
+        let streamed =
+            reg.collapse_db_files(&first_db_loc, &second_db_loc, &new_db_loc, &mut metrics)?;
+        let mut new_db = match (streamed, loaded) {
+            (true, _) => None,
+            (false, Some((first_db, second_db))) => {
+                Some(reg.collapse_dbs(first_db, second_db, &mut metrics))
             }
+            (false, None) => Some(reg.collapse_dbs(
+                reg.read_db(&first_db_loc)?,
+                reg.read_db(&second_db_loc)?,
+                &mut metrics,
+            )),
+        };
 
-            let first_friends = cycle.get_friends(&first_db, &data_route);
-            let second_friends = cycle.get_friends(&second_db, &data_route);
-
-            if !first_friends.is_empty() && !second_friends.is_empty() {
-                reg.produce_local(&mut new_queue, data_route);
-                continue;
+        let mut to_process = vec![];
+        if !inspected.is_empty() {
+            let merged_lookup = match new_db {
+                None => reg.open_lookup(&new_db_loc)?,
+                Some(_) => None,
+            };
+            if merged_lookup.is_none() && new_db.is_none() {
+                new_db = Some(reg.read_db(&new_db_loc)?);
             }
+            let loaded_merged = new_db.as_ref().map(|db| classifier.loaded(db));
+            let merged: &dyn RouteLookup<_> = match (&merged_lookup, &loaded_merged) {
+                (Some(merged), _) => merged.as_ref(),
+                (None, Some(merged)) => merged,
+                (None, None) => unreachable!("a merged database without a lookup is loaded"),
+            };
 
-            if (first_data_option.is_some()
-                && second_data_option.is_none()
-                && !first_friends.is_empty()
-                && second_friends.is_empty())
-                || (first_data_option.is_none()
-                    && second_data_option.is_some()
-                    && first_friends.is_empty()
-                    && !second_friends.is_empty())
-            {
-                reg.produce_local(&mut new_queue, data_route);
-                continue;
+            for (data_route, inspection) in inspected {
+                let cycle = reg.get_data_cycle(data_route.clone());
+                if classifier
+                    .classify(&cycle, inspection, merged, &data_route)?
+                    .needs_processing()
+                {
+                    to_process.push(data_route);
+                }
             }
-
-            // todo play the whole data cycle on second_queue
-            reg.produce_local(&mut new_queue, data_route);
         }
 
-        reg.write_db(&new_db_loc, &new_db)?;
+        // a streamed merge is already written, unless some of its routes are searched again
+        if !streamed || !to_process.is_empty() {
+            let mut new_db = match new_db {
+                Some(new_db) => new_db,
+                None => reg.read_db(&new_db_loc)?,
+            };
+            for data_route in to_process {
+                process_merged(
+                    &reg,
+                    &mut new_db,
+                    &mut new_provenance,
+                    &mut global_queue,
+                    data_route,
+                )?;
+            }
+            reg.write_db(&new_db_loc, &new_db)?;
+        }
+        reg.write_provenance(&new_db_loc, &new_provenance)?;
         reg.write_local_queue(&new_queue_loc, &new_queue)?;
+        reg.set_latest_db(&new_db_loc)?;
 
         reg.produce_merge_event(&mut global_queue, new_db_loc, new_queue_loc)?;
 
         reg.ack_global(&mut global_queue, first_merge_rec)?;
         reg.ack_global(&mut global_queue, second_merge_rec)?;
 
+        reg.delete_provenance(&first_db_loc)?;
+        reg.delete_provenance(&second_db_loc)?;
         reg.delete_db(first_db_loc).expect("panic here if we can't delete the DB. Otherwise it will be too late to come back to this as we have already acked. No, you cant just not ack until after because then it will retry and the DB will be gone");
         reg.delete_db(second_db_loc).expect("panic here if we can't delete the DB. Otherwise it will be too late to come back to this as we have already acked. No, you cant just not ack until after because then it will retry and the DB will be gone");
-        return Ok(());
+        return Ok(Some(metrics));
     }
 
     let name = reg.worker_name();
     let random_string = reg.unique_string();
-    let mut db = reg.create_db();
     let db_loc = reg.db_location(name.clone(), random_string)?;
 
-    let (data, global_receipt) = reg.consume_global(&mut global_queue)?;
+    if reg.steal_interval().is_some() && !reg.has_global_work(&mut global_queue)? {
+        reg.request_steal(&mut global_queue)?;
+    }
 
-    let mut local_queue = reg.create_local_queue();
-    let mut queue_to_write = reg.create_local_queue();
+    let (data, global_receipt) = reg.consume_global(&mut global_queue)?;
     let pre_cycle = reg.cycle_by_data(&data);
-    let pre_routes = pre_cycle.save(&mut db, vec![&data]);
-    for pre_route in pre_routes {
-        if pre_route.is_none() {
-            continue;
-        }
+    anyhow::ensure!(
+        pre_cycle.accepts(&data),
+        "job {} was routed to the cycle for {} data, which can't read it",
+        reg.data_id(&data),
+        pre_cycle.signature().input
+    );
+
+    // jobs see the published base, so only data missing from it is routed and explored
+    let base_loc = reg.base_db()?.map(|base| base.db);
+    let base = base_loc.as_ref().map(|loc| reg.read_db(loc)).transpose()?;
+    let base_provenance = match &base_loc {
+        Some(loc) => reg.read_provenance(loc)?,
+        None => ProvenanceLog::default(),
+    };
+
+    let checkpoint = reg.read_checkpoint(&global_receipt)?;
+    let resumed = checkpoint.is_some();
+    let Checkpoint {
+        db: delta,
+        mut local_queue,
+        mut queue_to_write,
+        mut provenance,
+    } = checkpoint.unwrap_or_else(|| Checkpoint {
+        db: reg.create_db(),
+        local_queue: reg.create_local_queue(),
+        queue_to_write: reg.create_local_queue(),
+        provenance: ProvenanceLog::default(),
+    });
+    let mut db = match base {
+        Some(base) => reg.layer_db(base, delta)?,
+        None => delta,
+    };
 
-        let pre_route = pre_route.unwrap();
-        reg.produce_local(&mut local_queue, pre_route);
+    // a job's input continues the lineage of whichever job published it, if that is known
+    let job_id = reg.data_id(&data);
+    let source_of = |provenance: &ProvenanceLog, id: u64| {
+        provenance
+            .get(id)
+            .or_else(|| base_provenance.get(id))
+            .map(|found| found.source)
+    };
+
+    if !resumed {
+        if let Some(pre_route) = pre_cycle.store(&mut db, &data) {
+            let source = source_of(&provenance, job_id).unwrap_or(job_id);
+            provenance.record(
+                job_id,
+                Provenance {
+                    job: job_id,
+                    worker: name.clone(),
+                    source,
+                    parent: None,
+                    friends: vec![],
+                },
+            );
+            reg.produce_local(&mut local_queue, pre_route, 0);
+        }
     }
+    let start_size = reg.db_size(&db);
 
+    let mut meter = reg.budget().start();
     while let Some(local_route) = reg.consume_local(&mut local_queue) {
-        reg.produce_local(&mut queue_to_write, local_route.clone());
+        if meter
+            .exhausted(reg.db_size(&db).saturating_sub(start_size))
+            .is_some()
+        {
+            let priority = route_priority(&reg, &db, &local_route);
+            reg.produce_local(&mut local_queue, local_route, priority);
+            let remaining = reg.local_queue_len(&local_queue);
+            hand_off(
+                &reg,
+                &db,
+                &provenance,
+                &mut local_queue,
+                &mut global_queue,
+                remaining,
+            )?;
+            break;
+        }
+        meter.record_route();
+
+        if reg
+            .steal_interval()
+            .is_some_and(|interval| meter.routes().is_multiple_of(interval))
+            && reg.take_steal_request(&mut global_queue)?
+        {
+            let half = reg.local_queue_len(&local_queue) / 2;
+            hand_off(
+                &reg,
+                &db,
+                &provenance,
+                &mut local_queue,
+                &mut global_queue,
+                half,
+            )?;
+        }
+
+        reg.produce_local(&mut queue_to_write, local_route.clone(), 0);
 
         // start datacycle
         let cycle = reg.get_data_cycle(local_route.clone());
@@ -238,26 +573,26 @@ pub fn run_worker(reg: impl Registry) -> Result<(), anyhow::Error> {
             .get_data(&db, &local_route)
             .context("attempting to get data")?;
 
-        cycle.save(&mut db, vec![&data]);
         if cycle.stop_categorically(&db) {
-            reg.ack_global(&mut global_queue, global_receipt)?;
-            return Ok(());
+            return finish_job(&reg, &mut global_queue, global_receipt).map(|()| None);
         }
 
         if cycle.stop_data(&data, &db) {
-            reg.ack_global(&mut global_queue, global_receipt)?;
-            return Ok(());
+            return finish_job(&reg, &mut global_queue, global_receipt).map(|()| None);
         }
 
         let friends = cycle.get_friends(&db, &local_route);
 
         if cycle.stop_friends(&friends) {
-            reg.ack_global(&mut global_queue, global_receipt)?;
-            return Ok(());
+            return finish_job(&reg, &mut global_queue, global_receipt).map(|()| None);
         }
 
         let results = cycle.search(&data, &friends);
 
+        let parent_id = reg.data_id(&data);
+        let friend_ids: Vec<u64> = friends.iter().map(|friend| reg.data_id(friend)).collect();
+        let source = source_of(&provenance, parent_id).unwrap_or(job_id);
+
         let to_pass = results.iter().collect();
         let res = cycle.save(&mut db, to_pass);
         for (search_result, search_location) in results.into_iter().zip(res) {
@@ -266,32 +601,1079 @@ pub fn run_worker(reg: impl Registry) -> Result<(), anyhow::Error> {
             }
 
             let search_location = search_location.unwrap();
+            provenance.record(
+                reg.data_id(&search_result),
+                Provenance {
+                    job: job_id,
+                    worker: name.clone(),
+                    source,
+                    parent: Some(parent_id),
+                    friends: friend_ids.clone(),
+                },
+            );
+            let priority = cycle.priority(&search_result);
 
             if cycle.public() {
-                reg.produce_global(search_result, &mut global_queue, 0)?;
+                publish_global(&reg, search_result, &mut global_queue, priority)?;
             } else {
-                reg.produce_local(&mut local_queue, search_location);
+                reg.produce_local(&mut local_queue, search_location, priority);
             }
         }
+
+        if reg
+            .checkpoint_interval()
+            .is_some_and(|interval| meter.routes().is_multiple_of(interval))
+        {
+            let checkpoint = Checkpoint {
+                db,
+                local_queue,
+                queue_to_write,
+                provenance,
+            };
+            reg.write_checkpoint(&global_receipt, &checkpoint)?;
+            (db, local_queue, queue_to_write, provenance) = (
+                checkpoint.db,
+                checkpoint.local_queue,
+                checkpoint.queue_to_write,
+                checkpoint.provenance,
+            );
+        }
     }
 
     // end datacycle
 
     reg.write_db(&db_loc, &db)?;
+    reg.write_provenance(&db_loc, &provenance)?;
     let local_queue_location = reg.queue_location(name, reg.unique_string())?;
     reg.write_local_queue(&local_queue_location, &queue_to_write)?;
+    reg.set_latest_db(&db_loc)?;
 
     reg.produce_merge_event(&mut global_queue, db_loc, local_queue_location)?;
-    reg.ack_global(&mut global_queue, global_receipt)?;
+    finish_job(&reg, &mut global_queue, global_receipt).map(|()| None)
+}
+
+// searches a route against the friends it gained in a merge, leaving what it finds to new jobs
+fn process_merged<R: Registry>(
+    reg: &R,
+    db: &mut R::Database,
+    provenance: &mut ProvenanceLog,
+    global_queue: &mut R::GlobalQueueLocation,
+    route: R::DataRoute,
+) -> Result<(), anyhow::Error> {
+    let cycle = reg.get_data_cycle(route.clone());
+    let data = cycle
+        .get_data(db, &route)
+        .context("attempting to get data")?;
+
+    if cycle.stop_categorically(db) || cycle.stop_data(&data, db) {
+        return Ok(());
+    }
+
+    let friends = cycle.get_friends(db, &route);
+    if cycle.stop_friends(&friends) {
+        return Ok(());
+    }
+
+    let results = cycle.search(&data, &friends);
+
+    let parent_id = reg.data_id(&data);
+    let parent = provenance.get(parent_id).cloned();
+    let friend_ids: Vec<u64> = friends.iter().map(|friend| reg.data_id(friend)).collect();
+
+    let saved = cycle.save(db, results.iter().collect());
+    for (search_result, search_location) in results.into_iter().zip(saved) {
+        if search_location.is_none() {
+            continue;
+        }
+
+        provenance.record(
+            reg.data_id(&search_result),
+            Provenance {
+                job: parent.as_ref().map_or(parent_id, |parent| parent.job),
+                worker: reg.worker_name(),
+                source: parent.as_ref().map_or(parent_id, |parent| parent.source),
+                parent: Some(parent_id),
+                friends: friend_ids.clone(),
+            },
+        );
+
+        // a merge has no local search of its own, so even private results go to new jobs
+        let priority = cycle.priority(&search_result);
+        if cycle.public() {
+            publish_global(reg, search_result, global_queue, priority)?;
+        } else {
+            reg.produce_global(search_result, global_queue, priority)?;
+        }
+    }
 
     Ok(())
 }
 
+/// Produces a job for data that wasn't published yet, then marks it published. A produce that
+/// fails leaves the data unmarked, so whoever finds it next publishes it; the duplicate job a
+/// worker publishing it in between may produce is harmless.
+pub(crate) fn publish_global<R: Registry>(
+    reg: &R,
+    data: R::Data,
+    global_queue: &mut R::GlobalQueueLocation,
+    priority: usize,
+) -> Result<(), anyhow::Error> {
+    if reg.is_published(&data)? {
+        return Ok(());
+    }
+    reg.produce_global(data.clone(), global_queue, priority)?;
+    reg.mark_published(&data)
+}
+
+fn finish_job<R: Registry>(
+    reg: &R,
+    global_queue: &mut R::GlobalQueueLocation,
+    receipt: R::JobReceipt,
+) -> Result<(), anyhow::Error> {
+    reg.delete_checkpoint(&receipt)?;
+    reg.ack_global(global_queue, receipt)
+}
+
+// the priority a route was queued with, which the local queue doesn't hand back
+fn route_priority<R: Registry>(reg: &R, db: &R::Database, route: &R::DataRoute) -> usize {
+    let cycle = reg.get_data_cycle(route.clone());
+    cycle
+        .get_data(db, route)
+        .map_or(0, |data| cycle.priority(&data))
+}
+
+// moves unexplored routes out of this job and back to the cluster as global jobs. Each job gets
+// a checkpoint holding what this job found so far, so the worker that takes it searches the route
+// with the same data and friends this one would have, instead of starting from the data alone
+fn hand_off<R: Registry>(
+    reg: &R,
+    db: &R::Database,
+    provenance: &ProvenanceLog,
+    local_queue: &mut R::LocalQueue,
+    global_queue: &mut R::GlobalQueueLocation,
+    count: usize,
+) -> Result<(), anyhow::Error> {
+    let mut routes = vec![];
+    for _ in 0..count {
+        let Some(route) = reg.consume_local(local_queue) else {
+            break;
+        };
+        let cycle = reg.get_data_cycle(route.clone());
+        if let Some(data) = cycle.get_data(db, &route) {
+            let priority = cycle.priority(&data);
+            routes.push((route, data, priority));
+        }
+    }
+    if routes.is_empty() {
+        return Ok(());
+    }
+
+    // databases can't be cloned, so each job's copy is read back from one saved for the hand-off
+    let saved = reg.db_location(reg.worker_name(), reg.unique_string())?;
+    reg.write_db(&saved, db)?;
+    for (route, data, priority) in routes {
+        let mut queue = reg.create_local_queue();
+        reg.produce_local(&mut queue, route, priority);
+        let checkpoint = Checkpoint {
+            db: reg.read_db(&saved)?,
+            local_queue: queue,
+            queue_to_write: reg.create_local_queue(),
+            provenance: provenance.clone(),
+        };
+
+        // a worker taking the job before its checkpoint is written starts from the data alone
+        let receipt = reg.produce_global(data, global_queue, priority)?;
+        reg.write_checkpoint(&receipt, &checkpoint)?;
+    }
+    reg.delete_db(saved)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::{BTreeSet, HashMap, VecDeque};
+    use std::rc::Rc;
+
+    #[derive(Clone, Debug, PartialEq, Hash)]
+    enum Data {
+        Count(u32),
+        // no cycle reads labels
+        Label(String),
+    }
+
+    crate::variant!(Data::Count(u32), Data::Label(String));
+
+    // a binary tree of numbers: n leads to 2n + 1 and 2n + 2, up to a limit
+    struct Tree {
+        limit: u32,
+        crash_at: Option<u32>,
+        public: bool,
+        // numbers are only searched next to their parent, the one friend they have
+        parents: bool,
+    }
+
+    fn parent(n: u32) -> Option<u32> {
+        n.checked_sub(1).map(|n| n / 2)
+    }
+
+    impl TypedCycle for Tree {
+        type Database = BTreeSet<u32>;
+        type DataRoute = u32;
+        type Data = Data;
+        type Input = u32;
+        type Friend = u32;
+        type Output = u32;
+
+        fn stop_categorically(&self, _db: &Self::Database) -> bool {
+            false
+        }
+
+        fn get_data(&self, db: &Self::Database, route: &u32) -> Option<u32> {
+            db.get(route).copied()
+        }
+
+        fn stop_data(&self, data: &u32, db: &Self::Database) -> bool {
+            self.parents && parent(*data).is_some_and(|parent| !db.contains(&parent))
+        }
+
+        fn get_friends(&self, db: &Self::Database, data: &u32) -> Vec<u32> {
+            parent(*data)
+                .filter(|parent| self.parents && db.contains(parent))
+                .into_iter()
+                .collect()
+        }
+
+        fn stop_friends(&self, _friends: &[u32]) -> bool {
+            false
+        }
+
+        fn search(&self, data: &u32, _friends: &[u32]) -> Vec<u32> {
+            if self.crash_at == Some(*data) {
+                panic!("worker crashed on {data}");
+            }
+
+            [data * 2 + 1, data * 2 + 2]
+                .into_iter()
+                .filter(|child| *child <= self.limit)
+                .collect()
+        }
+
+        fn store(&self, db: &mut Self::Database, data: &u32) -> Option<u32> {
+            db.insert(*data).then_some(*data)
+        }
+
+        fn save(&self, db: &mut Self::Database, new_data: Vec<&u32>) -> Vec<Option<u32>> {
+            new_data.into_iter().map(|n| self.store(db, n)).collect()
+        }
+
+        fn public(&self) -> bool {
+            self.public
+        }
+    }
+
+    // numbers befriend their neighbours, for classifying merges
+    struct Neighbours;
+
+    impl TypedCycle for Neighbours {
+        type Database = BTreeSet<u32>;
+        type DataRoute = u32;
+        type Data = Data;
+        type Input = u32;
+        type Friend = u32;
+        type Output = u32;
+
+        fn stop_categorically(&self, _db: &Self::Database) -> bool {
+            false
+        }
+
+        fn get_data(&self, db: &Self::Database, route: &u32) -> Option<u32> {
+            db.get(route).copied()
+        }
+
+        fn stop_data(&self, _data: &u32, _db: &Self::Database) -> bool {
+            false
+        }
+
+        fn get_friends(&self, db: &Self::Database, data: &u32) -> Vec<u32> {
+            [data.wrapping_sub(1), data + 1]
+                .into_iter()
+                .filter(|n| db.contains(n))
+                .collect()
+        }
+
+        fn stop_friends(&self, _friends: &[u32]) -> bool {
+            false
+        }
+
+        fn search(&self, _data: &u32, _friends: &[u32]) -> Vec<u32> {
+            vec![]
+        }
+
+        fn store(&self, db: &mut Self::Database, data: &u32) -> Option<u32> {
+            db.insert(*data).then_some(*data)
+        }
+
+        fn save(&self, db: &mut Self::Database, new_data: Vec<&u32>) -> Vec<Option<u32>> {
+            new_data.into_iter().map(|n| self.store(db, n)).collect()
+        }
+
+        fn public(&self) -> bool {
+            false
+        }
+    }
+
+    #[derive(Default)]
+    struct Cluster {
+        jobs: VecDeque<(Data, usize)>,
+        acked: Vec<usize>,
+        receipts: usize,
+        merges: VecDeque<(String, String, usize)>,
+        steals: usize,
+        dbs: HashMap<String, BTreeSet<u32>>,
+        queues: HashMap<String, LocalFrontier<u32>>,
+        checkpoints: HashMap<usize, RegistryCheckpoint<MemoryRegistry>>,
+        base: Option<BaseSnapshot<String>>,
+        latest: Option<String>,
+        provenance: HashMap<String, ProvenanceLog>,
+        // produces that fail before any job is queued
+        failing_produces: usize,
+        db_reads: usize,
+    }
+
+    // every worker shares one in-memory cluster
+    #[derive(Clone)]
+    struct MemoryRegistry {
+        cluster: Rc<RefCell<Cluster>>,
+        limit: u32,
+        budget: Budget,
+        steal_interval: Option<usize>,
+        checkpoint_interval: Option<usize>,
+        crash_at: Option<u32>,
+        public: bool,
+        parents: bool,
+        // merges stream databases and look routes up without reading them
+        streams: bool,
+        seen: Rc<RefCell<MemorySeenSet>>,
+    }
+
+    struct StoredLookup(BTreeSet<u32>);
+
+    impl RouteLookup<MemoryRegistry> for StoredLookup {
+        fn data(
+            &self,
+            cycle: &RegistryCycle<MemoryRegistry>,
+            route: &u32,
+        ) -> Result<Option<Data>, anyhow::Error> {
+            Ok(cycle.get_data(&self.0, route))
+        }
+
+        fn friends(
+            &self,
+            cycle: &RegistryCycle<MemoryRegistry>,
+            route: &u32,
+        ) -> Result<Vec<Data>, anyhow::Error> {
+            Ok(cycle.get_friends(&self.0, route))
+        }
+    }
+
+    impl MemoryRegistry {
+        fn new(limit: u32, jobs: Vec<u32>) -> Self {
+            let reg = MemoryRegistry {
+                cluster: Rc::default(),
+                limit,
+                budget: Budget::unlimited(),
+                steal_interval: None,
+                checkpoint_interval: None,
+                crash_at: None,
+                public: false,
+                parents: false,
+                streams: false,
+                seen: Rc::new(RefCell::new(MemorySeenSet::new(SeenMode::Exact))),
+            };
+            for job in jobs {
+                reg.produce_global(Data::Count(job), &mut (), 0).unwrap();
+            }
+            reg
+        }
+
+        fn receipt(&self) -> usize {
+            let mut cluster = self.cluster.borrow_mut();
+            cluster.receipts += 1;
+            cluster.receipts
+        }
+
+        // the databases named by pending merge events
+        fn shards(&self) -> Vec<BTreeSet<u32>> {
+            let cluster = self.cluster.borrow();
+            cluster
+                .merges
+                .iter()
+                .map(|(db_loc, _, _)| cluster.dbs[db_loc].clone())
+                .collect()
+        }
+
+        fn jobs(&self) -> Vec<Data> {
+            let cluster = self.cluster.borrow();
+            cluster.jobs.iter().map(|(data, _)| data.clone()).collect()
+        }
+
+        fn tree(&self) -> Box<Typed<Tree>> {
+            Box::new(Typed(Tree {
+                limit: self.limit,
+                crash_at: self.crash_at,
+                public: self.public,
+                parents: self.parents,
+            }))
+        }
+    }
+
+    impl Registry for MemoryRegistry {
+        type Database = BTreeSet<u32>;
+        type Location = String;
+        type GlobalQueueLocation = ();
+        type DataRoute = u32;
+        type JobReceipt = usize;
+        type LocalQueue = LocalFrontier<u32>;
+        type Data = Data;
+
+        fn unique_string(&self) -> String {
+            self.receipt().to_string()
+        }
+
+        fn worker_name(&self) -> String {
+            "memory".to_string()
+        }
+
+        fn create_db(&self) -> Self::Database {
+            BTreeSet::new()
+        }
+
+        fn db_location(
+            &self,
+            worker_name: String,
+            random_string: String,
+        ) -> Result<String, anyhow::Error> {
+            Ok(format!("database{worker_name}{random_string}"))
+        }
+
+        fn write_db(&self, loc: &String, db: &Self::Database) -> Result<(), anyhow::Error> {
+            self.cluster
+                .borrow_mut()
+                .dbs
+                .insert(loc.clone(), db.clone());
+            Ok(())
+        }
+
+        fn delete_db(&self, loc: String) -> Result<(), anyhow::Error> {
+            self.cluster
+                .borrow_mut()
+                .dbs
+                .remove(&loc)
+                .context("no such db")?;
+            Ok(())
+        }
+
+        fn read_db(&self, loc: &String) -> Result<Self::Database, anyhow::Error> {
+            self.cluster.borrow_mut().db_reads += 1;
+            self.cluster
+                .borrow()
+                .dbs
+                .get(loc)
+                .cloned()
+                .context("no such db")
+        }
+
+        fn read_queue(&self, loc: &String) -> Result<Self::LocalQueue, anyhow::Error> {
+            self.cluster
+                .borrow()
+                .queues
+                .get(loc)
+                .cloned()
+                .context("no such queue")
+        }
+
+        fn create_global_queue(&self) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        fn consume_global(&self, _queue: &mut ()) -> Result<(Data, usize), anyhow::Error> {
+            self.cluster
+                .borrow_mut()
+                .jobs
+                .pop_front()
+                .context("no jobs")
+        }
+
+        fn produce_global(
+            &self,
+            data: Data,
+            _queue: &mut (),
+            _priority: usize,
+        ) -> Result<usize, anyhow::Error> {
+            {
+                let mut cluster = self.cluster.borrow_mut();
+                if cluster.failing_produces > 0 {
+                    cluster.failing_produces -= 1;
+                    anyhow::bail!("the global queue is unreachable");
+                }
+            }
+            let receipt = self.receipt();
+            self.cluster.borrow_mut().jobs.push_back((data, receipt));
+            Ok(receipt)
+        }
+
+        fn ack_global(&self, _queue: &mut (), receipt: usize) -> Result<(), anyhow::Error> {
+            self.cluster.borrow_mut().acked.push(receipt);
+            Ok(())
+        }
+
+        fn create_local_queue(&self) -> Self::LocalQueue {
+            LocalFrontier::new(FrontierKind::Fifo)
+        }
+
+        fn consume_local(&self, queue: &mut Self::LocalQueue) -> Option<u32> {
+            queue.pop()
+        }
+
+        fn produce_local(&self, queue: &mut Self::LocalQueue, loc: u32, priority: usize) {
+            queue.push(loc, priority)
+        }
+
+        fn produce_merge_event(
+            &self,
+            _queue: &mut (),
+            db_loc: String,
+            queue_loc: String,
+        ) -> Result<usize, anyhow::Error> {
+            let receipt = self.receipt();
+            self.cluster
+                .borrow_mut()
+                .merges
+                .push_back((db_loc, queue_loc, receipt));
+            Ok(receipt)
+        }
+
+        fn consume_merge_event(
+            &self,
+            _queue: &mut (),
+        ) -> Result<Option<RegistryMergeEvent<Self>>, anyhow::Error> {
+            let mut cluster = self.cluster.borrow_mut();
+            if cluster.merges.len() < 2 {
+                return Ok(None);
+            }
+
+            let (first_db, first_queue, first_receipt) = cluster.merges.pop_front().unwrap();
+            let (second_db, second_queue, second_receipt) = cluster.merges.pop_front().unwrap();
+            Ok(Some(MergeEvent {
+                first_db,
+                first_queue,
+                first_receipt,
+                second_db,
+                second_queue,
+                second_receipt,
+            }))
+        }
+
+        fn collapse_dbs(
+            &self,
+            db: Self::Database,
+            other: Self::Database,
+            _metrics: &mut MergeMetrics,
+        ) -> Self::Database {
+            db.merged(other)
+        }
+
+        fn collapse_db_files(
+            &self,
+            first: &String,
+            second: &String,
+            out: &String,
+            _metrics: &mut MergeMetrics,
+        ) -> Result<bool, anyhow::Error> {
+            if !self.streams {
+                return Ok(false);
+            }
+            let mut cluster = self.cluster.borrow_mut();
+            let merged = cluster.dbs[first]
+                .clone()
+                .merged(cluster.dbs[second].clone());
+            cluster.dbs.insert(out.clone(), merged);
+            Ok(true)
+        }
+
+        fn open_lookup(
+            &self,
+            loc: &String,
+        ) -> Result<Option<Box<dyn RouteLookup<Self> + '_>>, anyhow::Error> {
+            if !self.streams {
+                return Ok(None);
+            }
+            let db = self.cluster.borrow().dbs.get(loc).cloned();
+            Ok(Some(Box::new(StoredLookup(db.context("no such db")?))))
+        }
+
+        fn queue_location(
+            &self,
+            worker_name: String,
+            random_string: String,
+        ) -> Result<String, anyhow::Error> {
+            Ok(format!("queue{worker_name}{random_string}"))
+        }
+
+        fn write_local_queue(
+            &self,
+            loc: &String,
+            queue: &Self::LocalQueue,
+        ) -> Result<(), anyhow::Error> {
+            self.cluster
+                .borrow_mut()
+                .queues
+                .insert(loc.clone(), queue.clone());
+            Ok(())
+        }
+
+        fn get_data_cycle(
+            &self,
+            _route: u32,
+        ) -> Box<dyn DataCycle<Database = Self::Database, DataRoute = u32, Data = Data>> {
+            self.tree()
+        }
+
+        fn cycle_by_data(
+            &self,
+            _data: &Data,
+        ) -> Box<dyn DataCycle<Database = Self::Database, DataRoute = u32, Data = Data>> {
+            self.tree()
+        }
+
+        fn cycles(&self) -> Vec<RegistryCycle<Self>> {
+            vec![self.tree()]
+        }
+
+        fn budget(&self) -> Budget {
+            self.budget
+        }
+
+        fn db_size(&self, db: &Self::Database) -> usize {
+            db.len()
+        }
+
+        fn local_queue_len(&self, queue: &Self::LocalQueue) -> usize {
+            queue.len()
+        }
+
+        fn has_global_work(&self, _queue: &mut ()) -> Result<bool, anyhow::Error> {
+            Ok(!self.cluster.borrow().jobs.is_empty())
+        }
+
+        fn request_steal(&self, _queue: &mut ()) -> Result<(), anyhow::Error> {
+            self.cluster.borrow_mut().steals += 1;
+            Ok(())
+        }
+
+        fn take_steal_request(&self, _queue: &mut ()) -> Result<bool, anyhow::Error> {
+            let mut cluster = self.cluster.borrow_mut();
+            if cluster.steals == 0 {
+                return Ok(false);
+            }
+            cluster.steals -= 1;
+            Ok(true)
+        }
+
+        fn steal_interval(&self) -> Option<usize> {
+            self.steal_interval
+        }
+
+        fn checkpoint_interval(&self) -> Option<usize> {
+            self.checkpoint_interval
+        }
+
+        fn write_checkpoint(
+            &self,
+            receipt: &usize,
+            checkpoint: &RegistryCheckpoint<Self>,
+        ) -> Result<(), anyhow::Error> {
+            let mut cluster = self.cluster.borrow_mut();
+            cluster.checkpoints.insert(*receipt, checkpoint.clone());
+            Ok(())
+        }
+
+        fn read_checkpoint(
+            &self,
+            receipt: &usize,
+        ) -> Result<Option<RegistryCheckpoint<Self>>, anyhow::Error> {
+            Ok(self.cluster.borrow().checkpoints.get(receipt).cloned())
+        }
+
+        fn delete_checkpoint(&self, receipt: &usize) -> Result<(), anyhow::Error> {
+            self.cluster.borrow_mut().checkpoints.remove(receipt);
+            Ok(())
+        }
+
+        fn is_published(&self, data: &Data) -> Result<bool, anyhow::Error> {
+            self.seen.borrow_mut().contains(content_id(data))
+        }
+
+        fn mark_published(&self, data: &Data) -> Result<(), anyhow::Error> {
+            self.seen.borrow_mut().insert(content_id(data))?;
+            Ok(())
+        }
+
+        fn base_db(&self) -> Result<Option<BaseSnapshot<String>>, anyhow::Error> {
+            Ok(self.cluster.borrow().base.clone())
+        }
+
+        fn set_base_db(&self, base: &BaseSnapshot<String>) -> Result<(), anyhow::Error> {
+            self.cluster.borrow_mut().base = Some(base.clone());
+            Ok(())
+        }
+
+        fn layer_db(
+            &self,
+            base: Self::Database,
+            delta: Self::Database,
+        ) -> Result<Self::Database, anyhow::Error> {
+            Ok(base.merged(delta))
+        }
+
+        fn latest_db(&self) -> Result<Option<String>, anyhow::Error> {
+            Ok(self.cluster.borrow().latest.clone())
+        }
+
+        fn set_latest_db(&self, loc: &String) -> Result<(), anyhow::Error> {
+            self.cluster.borrow_mut().latest = Some(loc.clone());
+            Ok(())
+        }
+
+        fn db_routes(&self, db: &Self::Database) -> Vec<u32> {
+            db.iter().copied().collect()
+        }
+
+        fn data_id(&self, data: &Data) -> u64 {
+            match data {
+                Data::Count(n) => content_id(n),
+                Data::Label(label) => content_id(label),
+            }
+        }
+
+        fn write_provenance(
+            &self,
+            db_loc: &String,
+            log: &ProvenanceLog,
+        ) -> Result<(), anyhow::Error> {
+            self.cluster
+                .borrow_mut()
+                .provenance
+                .insert(db_loc.clone(), log.clone());
+            Ok(())
+        }
+
+        fn read_provenance(&self, db_loc: &String) -> Result<ProvenanceLog, anyhow::Error> {
+            let cluster = self.cluster.borrow();
+            Ok(cluster.provenance.get(db_loc).cloned().unwrap_or_default())
+        }
+
+        fn delete_provenance(&self, db_loc: &String) -> Result<(), anyhow::Error> {
+            self.cluster.borrow_mut().provenance.remove(db_loc);
+            Ok(())
+        }
+    }
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn it_searches_a_job_into_a_shard() {
+        let reg = MemoryRegistry::new(6, vec![0]);
+
+        run_worker(reg.clone()).unwrap();
+
+        assert_eq!(reg.shards(), vec![(0..=6).collect()]);
+        assert!(reg.jobs().is_empty());
+        assert_eq!(reg.cluster.borrow().acked.len(), 1);
+    }
+
+    #[test]
+    fn it_merges_two_shards() {
+        let reg = MemoryRegistry::new(6, vec![1, 2]);
+
+        assert_eq!(run_worker(reg.clone()).unwrap(), None);
+        run_worker(reg.clone()).unwrap();
+        reg.cluster.borrow_mut().db_reads = 0;
+        assert_eq!(
+            run_worker(reg.clone()).unwrap(),
+            Some(MergeMetrics::default())
+        );
+
+        assert_eq!(reg.shards(), vec![(1..=6).collect()]);
+        // each shard is read once, for both the classifier and the collapse
+        assert_eq!(reg.cluster.borrow().db_reads, 2);
+    }
+
+    #[test]
+    fn it_classifies_a_streamed_merge_without_reading_shards() {
+        let mut reg = MemoryRegistry::new(6, vec![1, 2]);
+        reg.streams = true;
+
+        run_worker(reg.clone()).unwrap();
+        run_worker(reg.clone()).unwrap();
+        let routes: usize = {
+            let cluster = reg.cluster.borrow();
+            let queues = cluster
+                .merges
+                .iter()
+                .map(|(_, queue, _)| &cluster.queues[queue]);
+            queues.map(|queue| reg.local_queue_len(queue)).sum()
+        };
+        reg.cluster.borrow_mut().db_reads = 0;
+        run_worker(reg.clone()).unwrap();
+
+        assert!(routes > 0);
+        assert_eq!(reg.shards(), vec![(1..=6).collect()]);
+        assert_eq!(reg.cluster.borrow().db_reads, 0);
+    }
+
+    #[test]
+    fn it_keeps_a_job_routed_to_a_cycle_that_cannot_read_it() {
+        let reg = MemoryRegistry::new(6, vec![]);
+        reg.produce_global(Data::Label("stray".to_string()), &mut (), 0)
+            .unwrap();
+
+        let e = run_worker(reg.clone()).unwrap_err();
+
+        assert!(e.to_string().contains("can't read it"));
+        assert!(reg.cluster.borrow().acked.is_empty());
+        assert!(reg.shards().is_empty());
+    }
+
+    #[test]
+    fn it_spills_the_frontier_when_over_budget() {
+        let mut reg = MemoryRegistry::new(6, vec![0]);
+        reg.budget.max_routes = Some(2);
+
+        run_worker(reg.clone()).unwrap();
+
+        assert_eq!(reg.shards(), vec![(0..=4).collect()]);
+        // 2 was next when the budget ran out, so it goes back behind the rest of the frontier
+        assert_eq!(
+            reg.jobs(),
+            vec![Data::Count(3), Data::Count(4), Data::Count(2)]
+        );
+    }
+
+    #[test]
+    fn it_hands_off_half_the_frontier_on_request() {
+        let mut reg = MemoryRegistry::new(14, vec![0]);
+        reg.steal_interval = Some(3);
+        reg.cluster.borrow_mut().steals = 1;
+
+        run_worker(reg.clone()).unwrap();
+
+        // the check happens on taking 2, when 3 and 4 are left, and 3 is handed off
+        assert_eq!(reg.jobs(), vec![Data::Count(3)]);
+        assert!(!reg.shards()[0].contains(&7));
+        assert_eq!(reg.cluster.borrow().steals, 0);
+    }
+
+    #[test]
+    fn it_explores_spilled_routes_with_their_friends_elsewhere() {
+        let mut reg = MemoryRegistry::new(6, vec![0]);
+        reg.budget.max_routes = Some(2);
+        reg.parents = true;
+
+        run_worker(reg.clone()).unwrap();
+        // 2 is only searched next to 0, which the job that takes it gets with it
+        let receipt = reg.cluster.borrow().jobs[2].1;
+        let checkpoint = reg.read_checkpoint(&receipt).unwrap().unwrap();
+        assert_eq!(checkpoint.db, (0..=4).collect());
+        assert_eq!(checkpoint.local_queue.clone().pop(), Some(2));
+        assert_eq!(checkpoint.local_queue.len(), 1);
+
+        while !reg.jobs().is_empty() || reg.cluster.borrow().merges.len() > 1 {
+            run_worker(reg.clone()).unwrap();
+        }
+        assert_eq!(reg.shards(), vec![(0..=6).collect()]);
+        assert!(reg.cluster.borrow().checkpoints.is_empty());
+    }
+
+    #[test]
+    fn it_explores_stolen_routes_on_the_stealing_worker() {
+        let mut reg = MemoryRegistry::new(14, vec![0]);
+        reg.steal_interval = Some(3);
+        reg.parents = true;
+        reg.cluster.borrow_mut().steals = 1;
+
+        run_worker(reg.clone()).unwrap();
+        assert_eq!(reg.jobs(), vec![Data::Count(3)]);
+        run_worker(reg.clone()).unwrap();
+
+        // 3 is searched next to 1, which the thief never found itself
+        let shards = reg.shards();
+        assert_eq!(shards[1], BTreeSet::from([0, 1, 2, 3, 4, 7, 8]));
+        assert!(!shards[0].contains(&7) && !shards[0].contains(&8));
+        assert_eq!(reg.cluster.borrow().acked.len(), 2);
+    }
+
+    #[test]
+    fn it_resumes_a_crashed_job_from_its_checkpoint() {
+        let mut reg = MemoryRegistry::new(6, vec![0]);
+        reg.checkpoint_interval = Some(1);
+        reg.crash_at = Some(2);
+        let job = reg.cluster.borrow().jobs[0].clone();
+
+        let crashed =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run_worker(reg.clone())));
+        assert!(crashed.is_err());
+
+        // the job comes back after its time to run, and the checkpoint taken after 1 is waiting
+        let checkpoint = reg.read_checkpoint(&job.1).unwrap().unwrap();
+        assert_eq!(checkpoint.db, (0..=4).collect());
+        assert_eq!(checkpoint.local_queue.len(), 3);
+        reg.cluster.borrow_mut().jobs.push_back(job.clone());
+        reg.crash_at = None;
+
+        run_worker(reg.clone()).unwrap();
+
+        assert_eq!(reg.shards(), vec![(0..=6).collect()]);
+        assert!(reg.read_checkpoint(&job.1).unwrap().is_none());
+    }
+
+    #[test]
+    fn it_starts_from_an_existing_checkpoint() {
+        let reg = MemoryRegistry::new(6, vec![0]);
+        let receipt = reg.cluster.borrow().jobs[0].1;
+        let mut local_queue = reg.create_local_queue();
+        local_queue.push(2, 0);
+        let checkpoint = Checkpoint {
+            db: BTreeSet::from([0, 1, 2, 99]),
+            local_queue,
+            queue_to_write: reg.create_local_queue(),
+            provenance: ProvenanceLog::default(),
+        };
+        reg.write_checkpoint(&receipt, &checkpoint).unwrap();
+
+        run_worker(reg.clone()).unwrap();
+
+        assert_eq!(reg.shards(), vec![BTreeSet::from([0, 1, 2, 5, 6, 99])]);
+    }
+
+    #[test]
+    fn it_publishes_each_datum_once() {
+        let mut reg = MemoryRegistry::new(2, vec![0, 0]);
+        reg.public = true;
+
+        run_worker(reg.clone()).unwrap();
+        run_worker(reg.clone()).unwrap();
+
+        assert_eq!(reg.jobs(), vec![Data::Count(1), Data::Count(2)]);
+    }
+
+    #[test]
+    fn it_publishes_again_after_a_failed_produce() {
+        let reg = MemoryRegistry::new(2, vec![]);
+        reg.cluster.borrow_mut().failing_produces = 1;
+
+        assert!(publish_global(&reg, Data::Count(1), &mut (), 0).is_err());
+        assert!(!reg.is_published(&Data::Count(1)).unwrap());
+
+        publish_global(&reg, Data::Count(1), &mut (), 0).unwrap();
+        publish_global(&reg, Data::Count(1), &mut (), 0).unwrap();
+        assert_eq!(reg.jobs(), vec![Data::Count(1)]);
+    }
+
+    #[test]
+    fn it_only_explores_what_an_increment_adds() {
+        let reg = MemoryRegistry::new(6, vec![1, 2]);
+        for _ in 0..3 {
+            run_worker(reg.clone()).unwrap();
+        }
+        let final_db = reg.cluster.borrow().merges[0].0.clone();
+
+        start_increment(&reg, &final_db, vec![Data::Count(0)]).unwrap();
+        run_worker(reg.clone()).unwrap();
+
+        // 1 and 2 are already in the base, so 0 is the only route the job explored
+        let cluster = reg.cluster.borrow();
+        let (_, queue_loc, _) = &cluster.merges[1];
+        let mut explored = cluster.queues[queue_loc].clone();
+        assert_eq!(explored.pop(), Some(0));
+        assert_eq!(explored.pop(), None);
+        drop(cluster);
+
+        run_worker(reg.clone()).unwrap();
+        assert_eq!(reg.shards(), vec![(0..=6).collect()]);
+    }
+
+    #[test]
+    fn it_records_why_each_datum_exists() {
+        let reg = MemoryRegistry::new(6, vec![1, 2]);
+        for _ in 0..3 {
+            run_worker(reg.clone()).unwrap();
+        }
+
+        let results = ResultReader::open(reg).unwrap();
+        let lineage = results.why(content_id(&5));
+
+        let parents: Vec<_> = lineage.iter().map(|found| found.parent).collect();
+        assert_eq!(parents, vec![Some(content_id(&2)), None]);
+        assert!(lineage
+            .iter()
+            .all(|found| found.job == content_id(&2) && found.source == content_id(&2)));
+        assert_eq!(lineage[0].worker, "memory");
+        assert_eq!(results.provenance().len(), 6);
+    }
+
+    #[test]
+    fn it_classifies_merged_routes_by_the_readme_diagram() {
+        let reg = MemoryRegistry::new(6, vec![]);
+        let cycle: RegistryCycle<MemoryRegistry> = Box::new(Typed(Neighbours));
+        let (own, other) = (BTreeSet::from([1, 2, 5]), BTreeSet::from([3, 5]));
+        let merged = own.clone().merged(other.clone());
+        let mut provenance = ProvenanceLog::default();
+        let classify = |provenance: &ProvenanceLog, route| {
+            let classifier = MergeClassifier::new(&reg, provenance);
+            let (own, other) = (classifier.loaded(&own), classifier.loaded(&other));
+            let inspection = classifier.inspect(&cycle, &own, &other, &route).unwrap();
+            classifier
+                .classify(&cycle, inspection, &classifier.loaded(&merged), &route)
+                .unwrap()
+        };
+
+        assert_eq!(classify(&provenance, 5), MergeClass::DataInBoth);
+        assert_eq!(classify(&provenance, 1), MergeClass::NoNewFriends);
+        assert_eq!(classify(&provenance, 2), MergeClass::Process);
+
+        for n in [2, 3] {
+            let found = Provenance {
+                job: content_id(&0),
+                worker: "memory".to_string(),
+                source: content_id(&0),
+                parent: None,
+                friends: vec![],
+            };
+            provenance.record(content_id(&n), found);
+        }
+        assert_eq!(classify(&provenance, 2), MergeClass::SharedProvenance);
+    }
+
+    #[test]
+    fn it_reads_results_from_the_latest_merge() {
+        let reg = MemoryRegistry::new(6, vec![1, 2]);
+        for _ in 0..3 {
+            run_worker(reg.clone()).unwrap();
+        }
+
+        let results = ResultReader::open(reg).unwrap();
+
+        assert_eq!(
+            results.all::<u32>().collect::<Vec<_>>(),
+            (1..=6).collect::<Vec<_>>()
+        );
+        assert_eq!(results.get::<u32>(content_id(&4)), Some(4));
+        assert_eq!(results.get::<u32>(content_id(&0)), None);
+    }
 }
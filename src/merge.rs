@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+
+use crate::{ProvenanceLog, Registry, RegistryCycle};
+
+/// What a merge does with a route one shard explored, following the merge diagram in the README.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeClass {
+    /// Both shards hold the datum, so each searched it against the friends it had.
+    DataInBoth,
+    /// It gains no friends from the other shard: every friend it has in the merged database
+    /// was already in its own.
+    NoNewFriends,
+    /// The friends it gains from the other shard grew from the same input it did.
+    SharedProvenance,
+    /// It gains friends it was never searched with, so the merge searches it again.
+    Process,
+}
+
+impl MergeClass {
+    pub fn needs_processing(&self) -> bool {
+        *self == MergeClass::Process
+    }
+}
+
+/// Finds the datum at a route and its friends in one database a merge classifies against.
+pub trait RouteLookup<R: Registry> {
+    fn data(
+        &self,
+        cycle: &RegistryCycle<R>,
+        route: &R::DataRoute,
+    ) -> Result<Option<R::Data>, anyhow::Error>;
+    fn friends(
+        &self,
+        cycle: &RegistryCycle<R>,
+        route: &R::DataRoute,
+    ) -> Result<Vec<R::Data>, anyhow::Error>;
+}
+
+/// Looks routes up in a database already in memory.
+pub struct DatabaseLookup<'a, R: Registry>(pub &'a R::Database);
+
+impl<R: Registry> RouteLookup<R> for DatabaseLookup<'_, R> {
+    fn data(
+        &self,
+        cycle: &RegistryCycle<R>,
+        route: &R::DataRoute,
+    ) -> Result<Option<R::Data>, anyhow::Error> {
+        Ok(cycle.get_data(self.0, route))
+    }
+
+    fn friends(
+        &self,
+        cycle: &RegistryCycle<R>,
+        route: &R::DataRoute,
+    ) -> Result<Vec<R::Data>, anyhow::Error> {
+        Ok(cycle.get_friends(self.0, route))
+    }
+}
+
+/// What the shards tell about a route, taken before they are collapsed into the merged database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inspection {
+    DataInBoth,
+    /// Its datum's id and the ids of the friends it had in its own shard.
+    OwnOnly {
+        data: u64,
+        known: HashSet<u64>,
+    },
+}
+
+/// Classifies the routes of two shards against the database they merge into, in two passes:
+/// `inspect` reads the shards and `classify` the merged database, so a collapse that consumes
+/// the shards can run in between.
+pub struct MergeClassifier<'a, R: Registry> {
+    reg: &'a R,
+    provenance: &'a ProvenanceLog,
+}
+
+impl<'a, R: Registry> MergeClassifier<'a, R> {
+    pub fn new(reg: &'a R, provenance: &'a ProvenanceLog) -> Self {
+        MergeClassifier { reg, provenance }
+    }
+
+    /// Looks routes up in a database this classifier's registry already holds in memory.
+    pub fn loaded<'d>(&self, db: &'d R::Database) -> DatabaseLookup<'d, R> {
+        DatabaseLookup(db)
+    }
+
+    /// Inspects a route explored in `own` against the `other` shard.
+    pub fn inspect(
+        &self,
+        cycle: &RegistryCycle<R>,
+        own: &dyn RouteLookup<R>,
+        other: &dyn RouteLookup<R>,
+        route: &R::DataRoute,
+    ) -> Result<Inspection, anyhow::Error> {
+        let data = own.data(cycle, route)?.context("attempting to get data")?;
+        if other.data(cycle, route)?.is_some() {
+            return Ok(Inspection::DataInBoth);
+        }
+
+        Ok(Inspection::OwnOnly {
+            data: self.reg.data_id(&data),
+            known: self.ids(own.friends(cycle, route)?),
+        })
+    }
+
+    /// Classifies an inspected route by the friends it has in the `merged` database.
+    pub fn classify(
+        &self,
+        cycle: &RegistryCycle<R>,
+        inspection: Inspection,
+        merged: &dyn RouteLookup<R>,
+        route: &R::DataRoute,
+    ) -> Result<MergeClass, anyhow::Error> {
+        let Inspection::OwnOnly { data, known } = inspection else {
+            return Ok(MergeClass::DataInBoth);
+        };
+
+        let gained: Vec<u64> = self
+            .ids(merged.friends(cycle, route)?)
+            .into_iter()
+            .filter(|id| !known.contains(id))
+            .collect();
+        if gained.is_empty() {
+            return Ok(MergeClass::NoNewFriends);
+        }
+
+        let source_of = |id: u64| self.provenance.get(id).map(|found| found.source);
+        let source = source_of(data);
+        if source.is_some() && gained.iter().all(|id| source_of(*id) == source) {
+            return Ok(MergeClass::SharedProvenance);
+        }
+
+        Ok(MergeClass::Process)
+    }
+
+    fn ids(&self, data: Vec<R::Data>) -> HashSet<u64> {
+        data.iter().map(|datum| self.reg.data_id(datum)).collect()
+    }
+}
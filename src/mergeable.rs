@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::Overlay;
+
+/// A database, or part of one, that two shards can be collapsed into.
+///
+/// Derive it for structs of tables with `#[derive(Mergeable)]`. Maps keep `other`'s value
+/// when both hold a key, which only matters if the key is not the value's content id.
+pub trait Mergeable {
+    fn merge(&mut self, other: Self);
+
+    fn merged(mut self, other: Self) -> Self
+    where
+        Self: Sized,
+    {
+        self.merge(other);
+        self
+    }
+}
+
+impl<K: Hash + Eq, V> Mergeable for HashMap<K, V> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+impl<T: Hash + Eq> Mergeable for HashSet<T> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+impl<K: Ord, V> Mergeable for BTreeMap<K, V> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+impl<T: Ord> Mergeable for BTreeSet<T> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+/// Treated as a set: items already present are skipped and order is kept.
+impl<T: PartialEq> Mergeable for Vec<T> {
+    fn merge(&mut self, other: Self) {
+        for item in other {
+            if !self.contains(&item) {
+                self.push(item);
+            }
+        }
+    }
+}
+
+/// Merges the deltas, keeping this overlay's base.
+impl<D: Mergeable> Mergeable for Overlay<D> {
+    fn merge(&mut self, other: Self) {
+        self.delta_mut().merge(other.into_delta());
+    }
+}
+
+macro_rules! tuple_mergeable {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Mergeable),+> Mergeable for ($($name,)+) {
+            fn merge(&mut self, other: Self) {
+                $(self.$index.merge(other.$index);)+
+            }
+        }
+    };
+}
+
+tuple_mergeable!(A 0);
+tuple_mergeable!(A 0, B 1);
+tuple_mergeable!(A 0, B 1, C 2);
+tuple_mergeable!(A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mergeable;
+
+    #[derive(Debug, Default, PartialEq, Mergeable)]
+    struct Tables {
+        names: HashMap<u64, String>,
+        tags: Vec<&'static str>,
+        pairs: (BTreeSet<u32>, HashSet<u32>),
+    }
+
+    #[test]
+    fn it_merges_collections_by_value() {
+        assert_eq!(vec![1, 2].merged(vec![2, 3]), vec![1, 2, 3]);
+        assert_eq!(
+            HashMap::from([(1, "a"), (2, "b")]).merged(HashMap::from([(2, "c")])),
+            HashMap::from([(1, "a"), (2, "c")])
+        );
+        assert_eq!(
+            (BTreeSet::from([1]), vec![1]).merged((BTreeSet::from([2]), vec![1])),
+            (BTreeSet::from([1, 2]), vec![1])
+        );
+    }
+
+    #[test]
+    fn it_derives_field_by_field_merges() {
+        let first = Tables {
+            names: HashMap::from([(1, "a".to_string())]),
+            tags: vec!["x"],
+            pairs: (BTreeSet::from([1]), HashSet::new()),
+        };
+        let second = Tables {
+            names: HashMap::from([(2, "b".to_string())]),
+            tags: vec!["x", "y"],
+            pairs: (BTreeSet::from([2]), HashSet::from([3])),
+        };
+
+        assert_eq!(
+            first.merged(second),
+            Tables {
+                names: HashMap::from([(1, "a".to_string()), (2, "b".to_string())]),
+                tags: vec!["x", "y"],
+                pairs: (BTreeSet::from([1, 2]), HashSet::from([3])),
+            }
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Codec, Header};
+
+type Upgrade = Box<dyn Fn(Codec, &[u8]) -> Result<Vec<u8>, anyhow::Error>>;
+
+/// Upgrades values persisted under older schema versions, one version at a time.
+///
+/// Steps are registered per kind of value, like a file kind or a shard table. A kind without
+/// a step for some version is read unchanged across it, so a version bump only needs steps
+/// for the types that changed shape.
+#[derive(Default)]
+pub struct Migrations {
+    steps: HashMap<(u32, String), Upgrade>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upgrades `kind` values written under schema version `from` to version `from + 1`.
+    pub fn step<Old: DeserializeOwned, New: Serialize>(
+        mut self,
+        from: u32,
+        kind: &str,
+        upgrade: impl Fn(Old) -> New + 'static,
+    ) -> Self {
+        let step = move |codec: Codec, payload: &[u8]| {
+            let old = codec
+                .decode(payload)
+                .context("the payload does not match the step's old type")?;
+            codec.encode(&upgrade(old))
+        };
+        self.steps.insert((from, kind.to_string()), Box::new(step));
+        self
+    }
+
+    /// Brings a payload written under `header` up to schema version `to`.
+    pub fn upgrade<'a>(
+        &self,
+        kind: &str,
+        header: Header,
+        to: u32,
+        payload: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, anyhow::Error> {
+        ensure!(
+            header.schema <= to,
+            "{kind} was written by schema version {}, newer than {to}",
+            header.schema
+        );
+
+        let mut payload = Cow::Borrowed(payload);
+        for from in header.schema..to {
+            if let Some(step) = self.steps.get(&(from, kind.to_string())) {
+                let upgraded = step(header.codec, &payload)
+                    .with_context(|| format!("migrating {kind} from schema version {from}"))?;
+                payload = Cow::Owned(upgraded);
+            }
+        }
+        Ok(payload)
+    }
+
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        header: Header,
+        to: u32,
+        payload: &[u8],
+    ) -> Result<T, anyhow::Error> {
+        header
+            .codec
+            .decode(&self.upgrade(kind, header, to, payload)?)
+    }
+}
+
+/// Loads every file in `dir` with `load`, failing with the names of those that don't load.
+///
+/// Keep files written by each released schema version as fixtures, and run this in tests so
+/// a type changing shape without a migration is caught before it reaches a running search.
+pub fn verify_fixtures<T>(
+    dir: &Path,
+    load: impl Fn(&Path) -> Result<T, anyhow::Error>,
+) -> Result<usize, anyhow::Error> {
+    let mut paths = fs::read_dir(dir)
+        .with_context(|| format!("reading fixtures in {}", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    paths.sort();
+
+    let failures = paths
+        .iter()
+        .filter_map(|path| {
+            load(path)
+                .err()
+                .map(|e| format!("{}: {e:#}", path.display()))
+        })
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        bail!("fixtures failed to load:\n{}", failures.join("\n"));
+    }
+    ensure!(!paths.is_empty(), "no fixtures in {}", dir.display());
+    Ok(paths.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct PathV0 {
+        edges: Vec<u32>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct PathV1 {
+        edges: Vec<u32>,
+        weight: u32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct PathV3 {
+        edges: Vec<u32>,
+        weight: u64,
+        label: String,
+    }
+
+    fn migrations() -> Migrations {
+        Migrations::new()
+            .step(0, "paths", |old: PathV0| PathV1 {
+                weight: old.edges.len() as u32,
+                edges: old.edges,
+            })
+            .step(2, "paths", |old: PathV1| PathV3 {
+                edges: old.edges,
+                weight: old.weight.into(),
+                label: String::new(),
+            })
+    }
+
+    #[test]
+    fn it_upgrades_through_every_step() {
+        for codec in [Codec::Bincode, Codec::Json] {
+            let header = Header::new(codec, 0);
+            let old = codec.encode(&PathV0 { edges: vec![4, 5] }).unwrap();
+
+            let read: PathV3 = migrations().decode("paths", header, 3, &old).unwrap();
+
+            assert_eq!(
+                read,
+                PathV3 {
+                    edges: vec![4, 5],
+                    weight: 2,
+                    label: String::new()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn it_leaves_other_kinds_and_current_payloads_alone() {
+        let header = Header::new(Codec::Bincode, 1);
+        let payload = bincode::serialize(&7u32).unwrap();
+
+        let upgraded = migrations().upgrade("queue", header, 3, &payload).unwrap();
+        assert!(matches!(upgraded, Cow::Borrowed(_)));
+        assert!(migrations()
+            .upgrade("queue", Header::new(Codec::Bincode, 4), 3, &payload)
+            .is_err());
+        assert!(migrations().upgrade("paths", header, 3, &payload).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Registry;
+
+/// A published, read-only database that jobs start from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaseSnapshot<Location> {
+    pub version: u64,
+    pub db: Location,
+}
+
+/// A job database layered over a shared read-only base.
+///
+/// Reads fall through to the base, writes only ever touch the delta, and only the delta
+/// is serialized, so a job persists what it found rather than a copy of the base.
+/// Tables are selected with accessors like `|db| &db.paths`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Overlay<D> {
+    base: Arc<D>,
+    delta: D,
+}
+
+impl<D: Default> Overlay<D> {
+    pub fn detached(delta: D) -> Self {
+        Overlay {
+            base: Arc::default(),
+            delta,
+        }
+    }
+}
+
+impl<D> Overlay<D> {
+    pub fn on(base: Arc<D>, delta: D) -> Self {
+        Overlay { base, delta }
+    }
+
+    pub fn base(&self) -> &Arc<D> {
+        &self.base
+    }
+
+    pub fn delta(&self) -> &D {
+        &self.delta
+    }
+
+    pub fn delta_mut(&mut self) -> &mut D {
+        &mut self.delta
+    }
+
+    pub fn into_delta(self) -> D {
+        self.delta
+    }
+
+    pub fn get<'a, K: Hash + Eq + 'a, V: 'a>(
+        &'a self,
+        table: impl Fn(&'a D) -> &'a HashMap<K, V>,
+        key: &K,
+    ) -> Option<&'a V> {
+        table(&self.delta)
+            .get(key)
+            .or_else(|| table(&self.base).get(key))
+    }
+
+    pub fn contains_key<'a, K: Hash + Eq + 'a, V: 'a>(
+        &'a self,
+        table: impl Fn(&'a D) -> &'a HashMap<K, V>,
+        key: &K,
+    ) -> bool {
+        self.get(table, key).is_some()
+    }
+
+    /// Base entries first, then the delta. The two never share a key.
+    pub fn iter<'a, K: 'a, V: 'a>(
+        &'a self,
+        table: impl Fn(&'a D) -> &'a HashMap<K, V>,
+    ) -> impl Iterator<Item = (&'a K, &'a V)> + Clone + 'a {
+        table(&self.base).iter().chain(table(&self.delta).iter())
+    }
+
+    pub fn values<'a, K: 'a, V: 'a>(
+        &'a self,
+        table: impl Fn(&'a D) -> &'a HashMap<K, V>,
+    ) -> impl Iterator<Item = &'a V> + Clone + 'a {
+        self.iter(table).map(|(_, value)| value)
+    }
+
+    pub fn len<'a, K: 'a, V: 'a>(&'a self, table: impl Fn(&'a D) -> &'a HashMap<K, V>) -> usize {
+        table(&self.base).len() + table(&self.delta).len()
+    }
+
+    pub fn is_empty<'a, K: 'a, V: 'a>(
+        &'a self,
+        table: impl Fn(&'a D) -> &'a HashMap<K, V>,
+    ) -> bool {
+        table(&self.base).is_empty() && table(&self.delta).is_empty()
+    }
+
+    /// Writes to the delta, returning false without writing if either layer has the key.
+    pub fn insert<K: Hash + Eq, V>(
+        &mut self,
+        table: impl Fn(&D) -> &HashMap<K, V>,
+        table_mut: impl FnOnce(&mut D) -> &mut HashMap<K, V>,
+        key: K,
+        value: V,
+    ) -> bool {
+        if table(&self.base).contains_key(&key) || table(&self.delta).contains_key(&key) {
+            return false;
+        }
+
+        table_mut(&mut self.delta).insert(key, value);
+        true
+    }
+}
+
+impl<D: Serialize> Serialize for Overlay<D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.delta.serialize(serializer)
+    }
+}
+
+impl<'de, D: Deserialize<'de> + Default> Deserialize<'de> for Overlay<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        D::deserialize(deserializer).map(Overlay::detached)
+    }
+}
+
+/// Snapshots the database at `db` as the next version of the base every job starts from.
+pub fn publish_base<R: Registry>(
+    reg: &R,
+    db: &R::Location,
+) -> Result<BaseSnapshot<R::Location>, anyhow::Error> {
+    let version = reg.base_db()?.map_or(0, |base| base.version + 1);
+
+    let snapshot = reg.read_db(db)?;
+    let snapshot_loc = reg.db_location(reg.worker_name(), reg.unique_string())?;
+    reg.write_db(&snapshot_loc, &snapshot)?;
+    reg.write_provenance(&snapshot_loc, &reg.read_provenance(db)?)?;
+
+    let base = BaseSnapshot {
+        version,
+        db: snapshot_loc,
+    };
+    reg.set_base_db(&base)?;
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Db {
+        words: HashMap<u64, String>,
+    }
+
+    fn db(words: &[(u64, &str)]) -> Db {
+        Db {
+            words: words
+                .iter()
+                .map(|(key, word)| (*key, word.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn it_reads_through_and_writes_to_the_delta() {
+        let mut overlay = Overlay::on(Arc::new(db(&[(1, "base")])), Db::default());
+
+        assert_eq!(overlay.get(|db| &db.words, &1).unwrap(), "base");
+        assert!(!overlay.insert(|db| &db.words, |db| &mut db.words, 1, "again".to_string()));
+        assert!(overlay.insert(|db| &db.words, |db| &mut db.words, 2, "delta".to_string()));
+
+        assert_eq!(overlay.len(|db| &db.words), 2);
+        assert_eq!(overlay.delta(), &db(&[(2, "delta")]));
+        assert_eq!(
+            overlay.values(|db| &db.words).collect::<Vec<_>>(),
+            vec!["base", "delta"]
+        );
+    }
+
+    #[test]
+    fn it_persists_only_the_delta() {
+        let mut overlay = Overlay::on(Arc::new(db(&[(1, "base")])), Db::default());
+        overlay.insert(|db| &db.words, |db| &mut db.words, 2, "delta".to_string());
+
+        let bytes = bincode::serialize(&overlay).unwrap();
+        let read: Overlay<Db> = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(bytes, bincode::serialize(&db(&[(2, "delta")])).unwrap());
+        assert!(read.base().words.is_empty());
+        assert_eq!(read.delta(), overlay.delta());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::Add;
+
+use crate::Mergeable;
+
+/// How a collapse resolves a key both shards hold.
+pub struct Policy<V> {
+    resolve: Box<dyn Fn(V, V) -> V>,
+}
+
+impl<V: 'static> Policy<V> {
+    /// The second shard's value wins, as it does without a policy.
+    pub fn last_writer_wins() -> Self {
+        Policy::custom(|_, other| other)
+    }
+
+    pub fn min_by_key<T: Ord>(key: impl Fn(&V) -> T + 'static) -> Self {
+        Policy::custom(move |value, other| {
+            if key(&other) < key(&value) {
+                other
+            } else {
+                value
+            }
+        })
+    }
+
+    pub fn max_by_key<T: Ord>(key: impl Fn(&V) -> T + 'static) -> Self {
+        Policy::custom(move |value, other| {
+            if key(&other) > key(&value) {
+                other
+            } else {
+                value
+            }
+        })
+    }
+
+    pub fn sum() -> Self
+    where
+        V: Add<Output = V>,
+    {
+        Policy::custom(|value: V, other| value + other)
+    }
+
+    /// Keeps both versions by merging values that are themselves collections.
+    pub fn union() -> Self
+    where
+        V: Mergeable,
+    {
+        Policy::custom(|value: V, other| value.merged(other))
+    }
+
+    pub fn custom(resolve: impl Fn(V, V) -> V + 'static) -> Self {
+        Policy {
+            resolve: Box::new(resolve),
+        }
+    }
+
+    pub(crate) fn resolve(&self, value: V, other: V) -> V {
+        (self.resolve)(value, other)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableMetrics {
+    /// Entries taken from the second shard.
+    pub merged: usize,
+    /// Keys both shards held, each resolved by the policy.
+    pub shared: usize,
+    /// Shared keys whose values differed.
+    pub conflicts: usize,
+}
+
+/// What one merge's collapse did, per table with a policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeMetrics {
+    pub tables: BTreeMap<&'static str, TableMetrics>,
+}
+
+impl MergeMetrics {
+    pub fn conflicts(&self) -> usize {
+        self.tables.values().map(|table| table.conflicts).sum()
+    }
+}
+
+type Resolver<D> = Box<dyn Fn(&mut D, &mut D, &mut TableMetrics)>;
+
+/// Per-table policies for collapsing two databases.
+///
+/// Tables with a policy are resolved by it. Everything else in the database is merged
+/// with its [`Mergeable`] implementation.
+pub struct CollapsePolicies<D> {
+    tables: Vec<(&'static str, Resolver<D>)>,
+}
+
+impl<D> Default for CollapsePolicies<D> {
+    fn default() -> Self {
+        CollapsePolicies { tables: vec![] }
+    }
+}
+
+impl<D: Mergeable + 'static> CollapsePolicies<D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn table<K: Hash + Eq + 'static, V: PartialEq + 'static>(
+        mut self,
+        name: &'static str,
+        table: fn(&mut D) -> &mut HashMap<K, V>,
+        policy: Policy<V>,
+    ) -> Self {
+        let resolver = move |db: &mut D, other: &mut D, metrics: &mut TableMetrics| {
+            let target = table(db);
+            for (key, value) in std::mem::take(table(other)) {
+                metrics.merged += 1;
+                match target.entry(key) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(value);
+                    }
+                    Entry::Occupied(entry) => {
+                        metrics.shared += 1;
+                        if *entry.get() != value {
+                            metrics.conflicts += 1;
+                        }
+                        let (key, existing) = entry.remove_entry();
+                        target.insert(key, policy.resolve(existing, value));
+                    }
+                }
+            }
+        };
+        self.tables.push((name, Box::new(resolver)));
+        self
+    }
+
+    pub fn collapse(&self, mut db: D, mut other: D, metrics: &mut MergeMetrics) -> D {
+        for (name, resolve) in &self.tables {
+            resolve(&mut db, &mut other, metrics.tables.entry(name).or_default());
+        }
+        db.merged(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[derive(Debug, Default, PartialEq, crate::Mergeable)]
+    struct Db {
+        shortest: HashMap<&'static str, String>,
+        counts: HashMap<&'static str, u32>,
+        tags: HashMap<&'static str, HashSet<&'static str>>,
+        rest: HashMap<&'static str, u32>,
+    }
+
+    fn policies() -> CollapsePolicies<Db> {
+        CollapsePolicies::<Db>::new()
+            .table(
+                "shortest",
+                |db| &mut db.shortest,
+                Policy::min_by_key(String::len),
+            )
+            .table("counts", |db| &mut db.counts, Policy::sum())
+            .table("tags", |db| &mut db.tags, Policy::union())
+    }
+
+    #[test]
+    fn it_resolves_conflicts_by_table_policy() {
+        let first = Db {
+            shortest: HashMap::from([("a", "long way".to_string())]),
+            counts: HashMap::from([("a", 2)]),
+            tags: HashMap::from([("a", HashSet::from(["x"]))]),
+            rest: HashMap::from([("a", 1)]),
+        };
+        let second = Db {
+            shortest: HashMap::from([("a", "short".to_string())]),
+            counts: HashMap::from([("a", 3), ("b", 1)]),
+            tags: HashMap::from([("a", HashSet::from(["y"]))]),
+            rest: HashMap::from([("a", 2)]),
+        };
+        let mut metrics = MergeMetrics::default();
+
+        let merged = policies().collapse(first, second, &mut metrics);
+
+        assert_eq!(merged.shortest["a"], "short");
+        assert_eq!(merged.counts, HashMap::from([("a", 5), ("b", 1)]));
+        assert_eq!(merged.tags["a"], HashSet::from(["x", "y"]));
+        assert_eq!(merged.rest["a"], 2);
+
+        assert_eq!(metrics.conflicts(), 3);
+        assert_eq!(
+            metrics.tables["counts"],
+            TableMetrics {
+                merged: 2,
+                shared: 1,
+                conflicts: 1
+            }
+        );
+    }
+
+    #[test]
+    fn it_resolves_identical_values_without_counting_a_conflict() {
+        let db = || Db {
+            counts: HashMap::from([("a", 1)]),
+            ..Db::default()
+        };
+        let mut metrics = MergeMetrics::default();
+
+        let merged = policies().collapse(db(), db(), &mut metrics);
+
+        assert_eq!(merged.counts["a"], 2);
+        assert_eq!(metrics.tables["counts"].shared, 1);
+        assert_eq!(metrics.conflicts(), 0);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+/// Where a saved datum came from. Data is referred to by `Registry::data_id`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The datum of the global job that saved it.
+    pub job: u64,
+    pub worker: String,
+    /// The input its lineage started from, as far back as the job could see.
+    pub source: u64,
+    /// The datum `search` ran on, or none for a job's own input.
+    pub parent: Option<u64>,
+    /// The friends `search` combined with the parent.
+    pub friends: Vec<u64>,
+}
+
+/// The provenance of everything saved to one database, stored alongside it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenanceLog {
+    entries: HashMap<u64, Provenance>,
+}
+
+impl ProvenanceLog {
+    pub fn get(&self, id: u64) -> Option<&Provenance> {
+        self.entries.get(&id)
+    }
+
+    /// Keeps the first record of a datum, unless a later one knows its parent.
+    pub fn record(&mut self, id: u64, provenance: Provenance) {
+        match self.entries.get(&id) {
+            Some(existing) if existing.parent.is_some() || provenance.parent.is_none() => {}
+            _ => {
+                self.entries.insert(id, provenance);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: ProvenanceLog) {
+        for (id, provenance) in other.entries {
+            self.record(id, provenance);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Why a datum exists: its own record, then its parent's, back to where the log runs out.
+    pub fn lineage(&self, id: u64) -> Vec<&Provenance> {
+        let mut seen = HashSet::new();
+        let mut lineage = vec![];
+        let mut next = Some(id);
+        while let Some(id) = next.filter(|id| seen.insert(*id)) {
+            let Some(provenance) = self.entries.get(&id) else {
+                break;
+            };
+            lineage.push(provenance);
+            next = provenance.parent;
+        }
+        lineage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(parent: Option<u64>) -> Provenance {
+        Provenance {
+            job: 0,
+            worker: "worker".to_string(),
+            source: 0,
+            parent,
+            friends: vec![],
+        }
+    }
+
+    #[test]
+    fn it_follows_parents_back_to_the_source() {
+        let mut log = ProvenanceLog::default();
+        log.record(0, found(None));
+        log.record(1, found(Some(0)));
+        log.record(2, found(Some(1)));
+
+        let parents: Vec<_> = log.lineage(2).iter().map(|p| p.parent).collect();
+        assert_eq!(parents, vec![Some(1), Some(0), None]);
+        assert!(log.lineage(3).is_empty());
+    }
+
+    #[test]
+    fn it_prefers_records_that_know_their_parent() {
+        let mut first = ProvenanceLog::default();
+        first.record(1, found(None));
+        let mut second = ProvenanceLog::default();
+        second.record(1, found(Some(0)));
+        second.record(2, found(Some(1)));
+
+        first.merge(second);
+
+        assert_eq!(first.get(1).unwrap().parent, Some(0));
+        assert_eq!(first.len(), 2);
+    }
+}
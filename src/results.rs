@@ -0,0 +1,94 @@
+use std::hash::Hash;
+
+use anyhow::Context;
+
+use crate::{content_id, Provenance, ProvenanceLog, Registry, Variant};
+
+/// Read access to the results of a finished search.
+pub struct ResultReader<R: Registry> {
+    reg: R,
+    db: R::Database,
+    routes: Vec<R::DataRoute>,
+    provenance: ProvenanceLog,
+}
+
+impl<R: Registry> ResultReader<R> {
+    /// Loads the latest database written, which is the final one once the queues have drained.
+    pub fn open(reg: R) -> Result<Self, anyhow::Error> {
+        let loc = reg
+            .latest_db()?
+            .context("no database has been written yet")?;
+        Self::at(reg, &loc)
+    }
+
+    pub fn at(reg: R, loc: &R::Location) -> Result<Self, anyhow::Error> {
+        let db = reg.read_db(loc)?;
+        let provenance = reg.read_provenance(loc)?;
+        Ok(Self::new(reg, db).with_provenance(provenance))
+    }
+
+    pub fn new(reg: R, db: R::Database) -> Self {
+        let routes = reg.db_routes(&db);
+        ResultReader {
+            reg,
+            db,
+            routes,
+            provenance: ProvenanceLog::default(),
+        }
+    }
+
+    pub fn with_provenance(self, provenance: ProvenanceLog) -> Self {
+        ResultReader { provenance, ..self }
+    }
+
+    pub fn db(&self) -> &R::Database {
+        &self.db
+    }
+
+    pub fn provenance(&self) -> &ProvenanceLog {
+        &self.provenance
+    }
+
+    /// How the datum with `data_id` was found, back to the job input it grew from.
+    pub fn why(&self, id: u64) -> Vec<&Provenance> {
+        self.provenance.lineage(id)
+    }
+
+    pub fn routes(&self) -> &[R::DataRoute] {
+        &self.routes
+    }
+
+    /// Every datum in the database, in route order.
+    pub fn data(&self) -> impl Iterator<Item = R::Data> + '_ {
+        self.routes.iter().filter_map(|route| {
+            self.reg
+                .get_data_cycle(route.clone())
+                .get_data(&self.db, route)
+        })
+    }
+
+    /// Every datum of one type.
+    pub fn all<V: Variant<R::Data>>(&self) -> impl Iterator<Item = V> + '_ {
+        self.data().filter_map(|data| V::from_data(&data))
+    }
+
+    /// The datum of one type whose `content_id` is `id`, looked up by key where the registry
+    /// has a `data_route` for it and found by a scan of that type otherwise.
+    pub fn get<V: Variant<R::Data> + Hash>(&self, id: u64) -> Option<V> {
+        let routes = V::names()
+            .into_iter()
+            .map(|name| self.reg.data_route(name, id))
+            .collect::<Option<Vec<_>>>();
+        let Some(routes) = routes else {
+            return self.all::<V>().find(|value| content_id(value) == id);
+        };
+
+        routes.into_iter().find_map(|route| {
+            let data = self
+                .reg
+                .get_data_cycle(route.clone())
+                .get_data(&self.db, &route)?;
+            V::from_data(&data)
+        })
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use twox_hash::XxHash64;
+
+/// A stable id for a datum, derived from its contents. It is persisted in seen sets, database
+/// keys and checkpoints, so it is hashed with xxHash64 rather than std's unspecified hasher.
+pub fn content_id<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The content ids of everything already published to the global queue.
+pub trait SeenSet {
+    /// Records an id, returning whether it had not been seen before.
+    fn insert(&mut self, id: u64) -> Result<bool, anyhow::Error>;
+    fn contains(&mut self, id: u64) -> Result<bool, anyhow::Error>;
+}
+
+/// How a seen set indexes ids in memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeenMode {
+    Exact,
+    /// Fixed memory, but a false positive wrongly suppresses a job that was never published.
+    Bloom {
+        expected_items: usize,
+        false_positive_rate: f64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-items * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0);
+        let hashes = ((bit_count / items) * ln2).round().max(1.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; (bit_count as usize).div_ceil(64)],
+            hashes,
+        }
+    }
+
+    // double hashing: the i-th probe is h1 + i * h2
+    fn probes(&self, id: u64) -> impl Iterator<Item = usize> + '_ {
+        let bit_count = (self.bits.len() * 64) as u64;
+        let second = content_id(&id) | 1;
+        (0..self.hashes as u64)
+            .map(move |i| (id.wrapping_add(i.wrapping_mul(second)) % bit_count) as usize)
+    }
+
+    pub fn insert(&mut self, id: u64) -> bool {
+        let probes: Vec<usize> = self.probes(id).collect();
+        let mut new = false;
+        for probe in probes {
+            let (word, bit) = (probe / 64, 1 << (probe % 64));
+            new |= self.bits[word] & bit == 0;
+            self.bits[word] |= bit;
+        }
+        new
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.probes(id)
+            .all(|probe| self.bits[probe / 64] & (1 << (probe % 64)) != 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum SeenIndex {
+    Exact(HashSet<u64>),
+    Bloom(BloomFilter),
+}
+
+impl SeenIndex {
+    fn new(mode: SeenMode) -> Self {
+        match mode {
+            SeenMode::Exact => SeenIndex::Exact(HashSet::new()),
+            SeenMode::Bloom {
+                expected_items,
+                false_positive_rate,
+            } => SeenIndex::Bloom(BloomFilter::new(expected_items, false_positive_rate)),
+        }
+    }
+
+    fn insert(&mut self, id: u64) -> bool {
+        match self {
+            SeenIndex::Exact(ids) => ids.insert(id),
+            SeenIndex::Bloom(filter) => filter.insert(id),
+        }
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        match self {
+            SeenIndex::Exact(ids) => ids.contains(&id),
+            SeenIndex::Bloom(filter) => filter.contains(id),
+        }
+    }
+}
+
+/// A seen set local to one process.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemorySeenSet {
+    index: SeenIndex,
+}
+
+impl MemorySeenSet {
+    pub fn new(mode: SeenMode) -> Self {
+        MemorySeenSet {
+            index: SeenIndex::new(mode),
+        }
+    }
+}
+
+impl SeenSet for MemorySeenSet {
+    fn insert(&mut self, id: u64) -> Result<bool, anyhow::Error> {
+        Ok(self.index.insert(id))
+    }
+
+    fn contains(&mut self, id: u64) -> Result<bool, anyhow::Error> {
+        Ok(self.index.contains(id))
+    }
+}
+
+/// A seen set shared by every worker that opens the same file.
+///
+/// Ids are appended as 8 byte records and each call first catches up on records other
+/// workers appended. Two workers publishing the same id at the same moment may both
+/// see it as new, so this narrows duplicates rather than ruling them out.
+#[derive(Debug)]
+pub struct FileSeenSet {
+    path: PathBuf,
+    file: File,
+    offset: u64,
+    index: SeenIndex,
+}
+
+impl FileSeenSet {
+    pub fn open(path: impl Into<PathBuf>, mode: SeenMode) -> Result<Self, anyhow::Error> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut seen = FileSeenSet {
+            path,
+            file,
+            offset: 0,
+            index: SeenIndex::new(mode),
+        };
+        seen.catch_up()?;
+        Ok(seen)
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn catch_up(&mut self) -> Result<(), anyhow::Error> {
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = vec![];
+        self.file.read_to_end(&mut buf)?;
+
+        // a record still being appended by another worker is picked up next time
+        let whole = buf.len() - buf.len() % 8;
+        for record in buf[..whole].chunks_exact(8) {
+            self.index.insert(u64::from_le_bytes(
+                record.try_into().expect("8 byte record"),
+            ));
+        }
+        self.offset += whole as u64;
+        Ok(())
+    }
+}
+
+impl SeenSet for FileSeenSet {
+    fn insert(&mut self, id: u64) -> Result<bool, anyhow::Error> {
+        self.catch_up()?;
+        if !self.index.insert(id) {
+            return Ok(false);
+        }
+
+        self.file.write_all(&id.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(true)
+    }
+
+    fn contains(&mut self, id: u64) -> Result<bool, anyhow::Error> {
+        self.catch_up()?;
+        Ok(self.index.contains(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_derives_the_same_content_ids_on_every_toolchain() {
+        assert_eq!(content_id(&42u64), 13066772586158965587);
+        assert_eq!(content_id("silkworm"), 17953599660161778937);
+        assert_ne!(content_id(&(1u32, 2u32)), content_id(&(2u32, 1u32)));
+    }
+
+    #[test]
+    fn it_remembers_ids_in_memory() {
+        for mode in [
+            SeenMode::Exact,
+            SeenMode::Bloom {
+                expected_items: 100,
+                false_positive_rate: 0.01,
+            },
+        ] {
+            let mut seen = MemorySeenSet::new(mode);
+
+            assert!(seen.insert(7).unwrap());
+            assert!(!seen.insert(7).unwrap());
+            assert!(seen.contains(7).unwrap());
+            assert!(!seen.contains(8).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_keeps_bloom_false_positives_near_the_target_rate() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        for id in 0..1000 {
+            filter.insert(content_id(&id));
+        }
+
+        let false_positives = (1000..11000)
+            .filter(|id| filter.contains(content_id(id)))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn it_shares_ids_between_workers_through_a_file() {
+        let path = std::env::temp_dir().join(format!("silkworm-seen-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut first = FileSeenSet::open(&path, SeenMode::Exact).unwrap();
+        let mut second = FileSeenSet::open(&path, SeenMode::Exact).unwrap();
+
+        assert!(first.insert(1).unwrap());
+        assert!(!second.insert(1).unwrap());
+        assert!(second.insert(2).unwrap());
+        assert!(first.contains(2).unwrap());
+
+        let reopened = FileSeenSet::open(&path, SeenMode::Exact);
+        assert!(reopened.unwrap().contains(1).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,721 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{bail, ensure, Context};
+use memmap2::Mmap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::store::{commit, temp_path};
+use crate::verify::{check, checksum};
+use crate::{Corruption, Header, MergeMetrics, Migrations, Policy, StorageMetrics};
+
+/// Ends every shard, after its index.
+pub(crate) const MAGIC: &[u8; 8] = b"SILKSHD1";
+/// Records between entries of a table's sparse index.
+const INDEX_EVERY: u64 = 64;
+
+/// The key and length before each value, then its checksum if the header asks for one.
+fn record_header(checksummed: bool) -> usize {
+    if checksummed {
+        16
+    } else {
+        12
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TableIndex {
+    name: String,
+    start: u64,
+    end: u64,
+    count: u64,
+    /// The key and offset of every `INDEX_EVERY`th record.
+    sparse: Vec<(u64, u64)>,
+}
+
+/// Writes a shard: a [`Header`], named tables of records sorted by key, then an index of the tables.
+///
+/// Values are encoded with the header's codec and each compressed on its own, so they can still
+/// be read one at a time. The index is always uncompressed bincode. When the header asks for
+/// checksums, each record and the index carry a crc32 so damage is found where it is read. Records are
+/// streamed to a temporary file that `finish` renames into place, so only the index is held
+/// in memory. A writer dropped before it finishes removes its temporary file.
+pub struct ShardWriter {
+    loc: PathBuf,
+    temp: PathBuf,
+    header: Header,
+    out: BufWriter<File>,
+    offset: u64,
+    tables: Vec<TableIndex>,
+    last_key: Option<u64>,
+    storage: StorageMetrics,
+    finished: bool,
+}
+
+impl ShardWriter {
+    pub fn create(loc: impl Into<PathBuf>, header: Header) -> Result<Self, anyhow::Error> {
+        let loc = loc.into();
+        let temp = temp_path(&loc)?;
+        let mut out = BufWriter::new(
+            File::create(&temp).with_context(|| format!("creating {}", temp.display()))?,
+        );
+        out.write_all(&header.to_bytes())?;
+
+        Ok(ShardWriter {
+            loc,
+            temp,
+            header,
+            out,
+            offset: Header::LEN as u64,
+            tables: vec![],
+            last_key: None,
+            storage: StorageMetrics::default(),
+            finished: false,
+        })
+    }
+
+    /// Starts a table. Records pushed after this belong to it until the next table starts.
+    pub fn begin_table(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        ensure!(
+            self.tables.iter().all(|table| table.name != name),
+            "table {name} was already written"
+        );
+        self.tables.push(TableIndex {
+            name: name.to_string(),
+            start: self.offset,
+            end: self.offset,
+            count: 0,
+            sparse: vec![],
+        });
+        self.last_key = None;
+        Ok(())
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Appends an encoded and compressed record to the current table. Keys must be strictly
+    /// increasing.
+    pub fn push(&mut self, key: u64, value: &[u8]) -> Result<(), anyhow::Error> {
+        let Some(table) = self.tables.last_mut() else {
+            bail!("records must be pushed into a table");
+        };
+        if let Some(last) = self.last_key {
+            ensure!(
+                key > last,
+                "keys of table {} must increase, but {key} follows {last}",
+                table.name
+            );
+        }
+        let len = u32::try_from(value.len()).context("records are at most 4GiB")?;
+
+        if table.count % INDEX_EVERY == 0 {
+            table.sparse.push((key, self.offset));
+        }
+        let mut record = [0; 12];
+        record[..8].copy_from_slice(&key.to_le_bytes());
+        record[8..].copy_from_slice(&len.to_le_bytes());
+        self.out.write_all(&record)?;
+        if self.header.checksum {
+            self.out
+                .write_all(&checksum(&[&record, value]).to_le_bytes())?;
+        }
+        self.out.write_all(value)?;
+
+        self.offset += (record_header(self.header.checksum) + value.len()) as u64;
+        self.storage += StorageMetrics {
+            raw: self
+                .header
+                .compression
+                .decompressed_len(value)
+                .unwrap_or(value.len()) as u64,
+            stored: value.len() as u64,
+        };
+        table.end = self.offset;
+        table.count += 1;
+        self.last_key = Some(key);
+        Ok(())
+    }
+
+    pub fn push_value<V: Serialize>(&mut self, key: u64, value: &V) -> Result<(), anyhow::Error> {
+        self.push(key, &encode_record(self.header, value)?)
+    }
+
+    /// Writes a whole table from records in any order.
+    pub fn write_table<'a, V: Serialize + 'a>(
+        &mut self,
+        name: &str,
+        records: impl IntoIterator<Item = (&'a u64, &'a V)>,
+    ) -> Result<(), anyhow::Error> {
+        let mut records = records.into_iter().collect::<Vec<_>>();
+        records.sort_unstable_by_key(|(key, _)| **key);
+
+        self.begin_table(name)?;
+        for (key, value) in records {
+            self.push_value(*key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index and renames the shard into place, returning how much compression saved.
+    pub fn finish(mut self) -> Result<StorageMetrics, anyhow::Error> {
+        let index = bincode::serialize(&self.tables)?;
+        self.out.write_all(&index)?;
+        self.out.write_all(&(index.len() as u64).to_le_bytes())?;
+        if self.header.checksum {
+            self.out.write_all(&checksum(&[&index]).to_le_bytes())?;
+        }
+        self.out.write_all(MAGIC)?;
+
+        self.out.flush()?;
+        self.out.get_ref().sync_all()?;
+        commit(&self.temp, &self.loc).with_context(|| format!("writing {}", self.loc.display()))?;
+        self.finished = true;
+
+        // everything but the records is written as is
+        let trailer = trailer_len(self.header) + index.len();
+        let overhead = self.offset + trailer as u64 - self.storage.stored;
+        Ok(StorageMetrics {
+            raw: self.storage.raw + overhead,
+            stored: self.storage.stored + overhead,
+        })
+    }
+}
+
+impl Drop for ShardWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// A memory-mapped shard. Tables are read lazily, so only the pages touched are loaded.
+pub struct Shard {
+    map: Mmap,
+    header: Header,
+    tables: Vec<TableIndex>,
+    migrations: Option<(Rc<Migrations>, u32)>,
+}
+
+impl Shard {
+    pub fn open(loc: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::open(loc).with_context(|| format!("opening {}", loc.display()))?;
+        // SAFETY: shards are only ever replaced by renaming, never modified in place
+        let map = unsafe { Mmap::map(&file) }?;
+        let (header, tables) =
+            Self::read_index(&map).with_context(|| format!("reading {}", loc.display()))?;
+
+        Ok(Shard {
+            map,
+            header,
+            tables,
+            migrations: None,
+        })
+    }
+
+    /// Upgrades values to schema version `schema` as they are decoded, each table as its own kind.
+    pub fn migrated(self, migrations: Rc<Migrations>, schema: u32) -> Self {
+        Shard {
+            migrations: Some((migrations, schema)),
+            ..self
+        }
+    }
+
+    fn decode<V: DeserializeOwned>(&self, table: &str, value: &[u8]) -> Result<V, anyhow::Error> {
+        match &self.migrations {
+            Some((migrations, schema)) => {
+                let value = self.header.compression.decompress(value)?;
+                migrations.decode(table, self.header, *schema, &value)
+            }
+            None => decode_record(self.header, value),
+        }
+    }
+
+    fn read_index(map: &[u8]) -> Result<(Header, Vec<TableIndex>), anyhow::Error> {
+        let (header, _) = Header::parse(map)?;
+        let trailer = trailer_len(header);
+        // a shard cut short loses its trailer first
+        if map.len() < Header::LEN + trailer || !map.ends_with(MAGIC) {
+            return Err(Corruption::Truncated.into());
+        }
+
+        let len_at = map.len() - trailer;
+        let index_len = u64::from_le_bytes(map[len_at..len_at + 8].try_into()?) as usize;
+        let index_at = len_at
+            .checked_sub(index_len)
+            .filter(|at| *at >= Header::LEN)
+            .ok_or(Corruption::Truncated)?;
+        let index = &map[index_at..len_at];
+        if header.checksum {
+            let expected = u32::from_le_bytes(map[len_at + 8..len_at + 12].try_into()?);
+            check(expected, checksum(&[index]))?;
+        }
+        let tables: Vec<TableIndex> = bincode::deserialize(index)
+            .map_err(|e| Corruption::Malformed(format!("unreadable shard index: {e}")))?;
+
+        for table in &tables {
+            if table.start > table.end || table.end > index_at as u64 {
+                let outside = format!("table {} lies outside the shard", table.name);
+                return Err(Corruption::Malformed(outside).into());
+            }
+        }
+        Ok((header, tables))
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    fn table(&self, name: &str) -> Option<&TableIndex> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &str> {
+        self.tables.iter().map(|table| table.name.as_str())
+    }
+
+    pub fn len(&self, table: &str) -> usize {
+        self.table(table).map_or(0, |table| table.count as usize)
+    }
+
+    pub fn is_empty(&self, table: &str) -> bool {
+        self.len(table) == 0
+    }
+
+    /// The records of a table in key order, empty if the shard has no such table.
+    pub fn records(&self, table: &str) -> Records<'_> {
+        let bytes = self.table(table).map_or(&[][..], |table| {
+            &self.map[table.start as usize..table.end as usize]
+        });
+        Records::new(bytes, self.header.checksum)
+    }
+
+    /// Finds a record by seeking to the nearest indexed key and scanning from there.
+    pub fn get(&self, table: &str, key: u64) -> Result<Option<&[u8]>, Corruption> {
+        let Some(table) = self.table(table) else {
+            return Ok(None);
+        };
+        let nearest = table.sparse.partition_point(|(indexed, _)| *indexed <= key);
+        let Some((_, from)) = nearest.checked_sub(1).and_then(|at| table.sparse.get(at)) else {
+            return Ok(None);
+        };
+
+        for record in Records::new(
+            &self.map[*from as usize..table.end as usize],
+            self.header.checksum,
+        ) {
+            let (found, value) = record?;
+            if found >= key {
+                return Ok((found == key).then_some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn values<'a, V: DeserializeOwned>(
+        &'a self,
+        table: &'a str,
+    ) -> impl Iterator<Item = Result<(u64, V), anyhow::Error>> + 'a {
+        self.records(table).map(move |record| {
+            let (key, value) = record?;
+            Ok((key, self.decode(table, value)?))
+        })
+    }
+
+    pub fn get_value<V: DeserializeOwned>(
+        &self,
+        table: &str,
+        key: u64,
+    ) -> Result<Option<V>, anyhow::Error> {
+        self.get(table, key)?
+            .map(|value| self.decode(table, value))
+            .transpose()
+    }
+
+    /// Reads every record of every table, checking checksums and key order. Returns how
+    /// many records there were.
+    pub fn verify(&self) -> Result<usize, Corruption> {
+        let mut verified = 0;
+        for table in &self.tables {
+            let count = self
+                .records(&table.name)
+                .try_fold(0, |count, record| record.map(|_| count + 1))?;
+            if count != table.count {
+                return Err(Corruption::Malformed(format!(
+                    "table {} holds {count} records but its index says {}",
+                    table.name, table.count
+                )));
+            }
+            verified += count as usize;
+        }
+        Ok(verified)
+    }
+}
+
+fn trailer_len(header: Header) -> usize {
+    8 + if header.checksum { 4 } else { 0 } + MAGIC.len()
+}
+
+/// Length-prefixed records read straight out of the mapped file, checked as they are read.
+///
+/// Iteration stops after the first corrupt record, since what follows it can't be found.
+pub struct Records<'a> {
+    bytes: &'a [u8],
+    checksummed: bool,
+    last: Option<u64>,
+}
+
+impl<'a> Records<'a> {
+    fn new(bytes: &'a [u8], checksummed: bool) -> Self {
+        Records {
+            bytes,
+            checksummed,
+            last: None,
+        }
+    }
+
+    fn read(&mut self) -> Result<(u64, &'a [u8]), Corruption> {
+        let header_len = record_header(self.checksummed);
+        let header = self.bytes.get(..header_len).ok_or(Corruption::Truncated)?;
+        let key = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let value = self
+            .bytes
+            .get(header_len..header_len + len)
+            .ok_or(Corruption::Truncated)?;
+
+        if self.checksummed {
+            let expected = u32::from_le_bytes(header[12..].try_into().unwrap());
+            check(expected, checksum(&[&header[..12], value]))?;
+        }
+        if let Some(last) = self.last.filter(|last| key <= *last) {
+            return Err(Corruption::Malformed(format!(
+                "record {key} follows record {last}"
+            )));
+        }
+        self.bytes = &self.bytes[header_len + len..];
+        self.last = Some(key);
+        Ok((key, value))
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<(u64, &'a [u8]), Corruption>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let record = self.read();
+        if record.is_err() {
+            self.bytes = &[];
+        }
+        Some(record)
+    }
+}
+
+pub(crate) fn encode_record<V: Serialize>(
+    header: Header,
+    value: &V,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let encoded = header.codec.encode(value)?;
+    Ok(header.compression.compress(&encoded)?.into_owned())
+}
+
+pub(crate) fn decode_record<V: DeserializeOwned>(
+    header: Header,
+    value: &[u8],
+) -> Result<V, anyhow::Error> {
+    header.codec.decode(&header.compression.decompress(value)?)
+}
+
+pub(crate) type ShardResolver = Box<dyn Fn(Header, &[u8], &[u8]) -> Result<Vec<u8>, anyhow::Error>>;
+
+/// Per-table policies for streaming two shards together, or ingesting one `DatabaseStore` into
+/// another, like `CollapsePolicies` does in memory.
+///
+/// Keys both shards hold take the second shard's record unless their table has a policy.
+#[derive(Default)]
+pub struct ShardPolicies {
+    tables: HashMap<&'static str, ShardResolver>,
+}
+
+impl ShardPolicies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn table<V: Serialize + DeserializeOwned + 'static>(
+        mut self,
+        name: &'static str,
+        policy: Policy<V>,
+    ) -> Self {
+        let resolver = move |header: Header, value: &[u8], other: &[u8]| {
+            let resolved =
+                policy.resolve(decode_record(header, value)?, decode_record(header, other)?);
+            encode_record(header, &resolved)
+        };
+        self.tables.insert(name, Box::new(resolver));
+        self
+    }
+
+    pub(crate) fn resolver(&self, table: &str) -> Option<(&'static str, &ShardResolver)> {
+        self.tables
+            .get_key_value(table)
+            .map(|(name, resolve)| (*name, resolve))
+    }
+
+    pub(crate) fn tables(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tables.keys().copied()
+    }
+}
+
+/// Merges two shards into `out` one record at a time, recording what policies did in `metrics`.
+///
+/// Records are copied without decoding them, so all three must share a header. `out` is left
+/// for the caller to finish.
+pub fn merge_shards(
+    first: &Shard,
+    second: &Shard,
+    out: &mut ShardWriter,
+    policies: &ShardPolicies,
+    metrics: &mut MergeMetrics,
+) -> Result<(), anyhow::Error> {
+    ensure!(
+        first.header == out.header && second.header == out.header,
+        "can't stream shards written as {:?} and {:?} into {:?}",
+        first.header,
+        second.header,
+        out.header
+    );
+    for name in policies.tables() {
+        metrics.tables.entry(name).or_default();
+    }
+    let names = first
+        .tables()
+        .chain(second.tables())
+        .collect::<BTreeSet<_>>();
+
+    for name in names {
+        out.begin_table(name)?;
+        let policy = policies.resolver(name);
+        let mut records = first.records(name).peekable();
+        let mut others = second.records(name).peekable();
+
+        while let Some((key, value)) = next_record(&mut records, &mut others)? {
+            match (value, policy) {
+                (Next::Shared(value, other), Some((name, resolve))) => {
+                    let table = metrics.tables.entry(name).or_default();
+                    table.merged += 1;
+                    table.shared += 1;
+                    if value != other {
+                        table.conflicts += 1;
+                    }
+                    out.push(key, &resolve(out.header, value, other)?)?;
+                }
+                (Next::Shared(_, other), None) => out.push(key, other)?,
+                (Next::Second(other), Some((name, _))) => {
+                    metrics.tables.entry(name).or_default().merged += 1;
+                    out.push(key, other)?;
+                }
+                (Next::First(value) | Next::Second(value), _) => out.push(key, value)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Next<'a> {
+    First(&'a [u8]),
+    Second(&'a [u8]),
+    Shared(&'a [u8], &'a [u8]),
+}
+
+fn peek_key(records: &mut Peekable<Records<'_>>) -> Result<Option<u64>, Corruption> {
+    match records.peek() {
+        Some(Ok((key, _))) => Ok(Some(*key)),
+        Some(Err(e)) => Err(e.clone()),
+        None => Ok(None),
+    }
+}
+
+fn next_record<'a>(
+    records: &mut Peekable<Records<'a>>,
+    others: &mut Peekable<Records<'a>>,
+) -> Result<Option<(u64, Next<'a>)>, Corruption> {
+    Ok(match (peek_key(records)?, peek_key(others)?) {
+        (Some(key), Some(other)) if key == other => records
+            .next()
+            .transpose()?
+            .zip(others.next().transpose()?)
+            .map(|((key, value), (_, other))| (key, Next::Shared(value, other))),
+        (Some(key), Some(other)) if other < key => others
+            .next()
+            .transpose()?
+            .map(|(key, other)| (key, Next::Second(other))),
+        (Some(_), _) => records
+            .next()
+            .transpose()?
+            .map(|(key, value)| (key, Next::First(value))),
+        (None, _) => others
+            .next()
+            .transpose()?
+            .map(|(key, other)| (key, Next::Second(other))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, Compression, TableMetrics};
+    use std::fs;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("silkworm-shard-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(loc: &Path, header: Header, tables: &[(&str, HashMap<u64, u32>)]) {
+        let mut writer = ShardWriter::create(loc, header).unwrap();
+        for (name, records) in tables {
+            writer.write_table(name, records).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn it_reads_tables_lazily_in_key_order() {
+        let dir = dir("read");
+        let loc = dir.join("shard");
+        let squares = (0..200u64).rev().map(|n| (n * 2, (n * n) as u32)).collect();
+        write(
+            &loc,
+            Header::new(Codec::Json, 1),
+            &[("squares", squares), ("empty", HashMap::new())],
+        );
+
+        let shard = Shard::open(&loc).unwrap();
+        assert_eq!(shard.tables().collect::<Vec<_>>(), vec!["squares", "empty"]);
+        assert_eq!(shard.header(), Header::new(Codec::Json, 1));
+        assert_eq!(shard.len("squares"), 200);
+        assert!(shard.is_empty("empty") && shard.is_empty("missing"));
+
+        let keys = shard
+            .records("squares")
+            .map(|record| record.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (0..200).map(|n| n * 2).collect::<Vec<_>>());
+        assert_eq!(
+            shard.get_value::<u32>("squares", 300).unwrap(),
+            Some(150 * 150)
+        );
+        assert_eq!(shard.get_value::<u32>("squares", 0).unwrap(), Some(0));
+        assert_eq!(shard.get_value::<u32>("squares", 301).unwrap(), None);
+        assert_eq!(shard.get_value::<u32>("squares", 1000).unwrap(), None);
+
+        let mut writer = ShardWriter::create(dir.join("unsorted"), Header::default()).unwrap();
+        assert!(writer.push(1, b"").is_err());
+        writer.begin_table("t").unwrap();
+        writer.push(2, b"").unwrap();
+        assert!(writer.push(1, b"").is_err());
+        assert!(Shard::open(&dir.join("unsorted")).is_err());
+        // an abandoned writer leaves no temporary file behind
+        drop(writer);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_detects_damaged_records() {
+        let dir = dir("damaged");
+        let loc = dir.join("shard");
+        let counts = (0..100u64).map(|n| (n, n as u32)).collect();
+        write(&loc, Header::new(Codec::Bincode, 1), &[("counts", counts)]);
+        let bytes = fs::read(&loc).unwrap();
+        assert_eq!(Shard::open(&loc).unwrap().verify(), Ok(100));
+
+        // the value of the 51st record, past the second sparse index entry
+        let mut flipped = bytes.clone();
+        flipped[Header::LEN + 50 * 20 + 16] ^= 1;
+        fs::write(&loc, &flipped).unwrap();
+        let shard = Shard::open(&loc).unwrap();
+        assert!(matches!(
+            shard.verify(),
+            Err(Corruption::ChecksumMismatch { .. })
+        ));
+        assert_eq!(shard.get_value::<u32>("counts", 49).unwrap(), Some(49));
+        assert!(shard.get_value::<u32>("counts", 50).is_err());
+        let read = shard.values::<u32>("counts").collect::<Vec<_>>();
+        assert_eq!(read.len(), 51);
+        assert!(read[50].is_err());
+
+        fs::write(&loc, &bytes[..bytes.len() - 3]).unwrap();
+        let e = Shard::open(&loc).err().unwrap();
+        assert_eq!(e.downcast_ref::<Corruption>(), Some(&Corruption::Truncated));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_streams_two_shards_into_a_third() {
+        let dir = dir("merge");
+        let header = Header::new(Codec::Json, 1).with_compression(Compression::Lz4);
+        write(
+            &dir.join("first"),
+            header,
+            &[
+                ("counts", HashMap::from([(1, 2), (3, 1)])),
+                ("rest", HashMap::from([(1, 1)])),
+            ],
+        );
+        write(
+            &dir.join("second"),
+            header,
+            &[
+                ("counts", HashMap::from([(1, 3), (2, 1), (3, 1)])),
+                ("rest", HashMap::from([(1, 2)])),
+                ("new", HashMap::from([(5, 5)])),
+            ],
+        );
+        let policies = ShardPolicies::new().table("counts", Policy::<u32>::sum());
+        let mut metrics = MergeMetrics::default();
+
+        let mut out = ShardWriter::create(dir.join("merged"), header).unwrap();
+        merge_shards(
+            &Shard::open(&dir.join("first")).unwrap(),
+            &Shard::open(&dir.join("second")).unwrap(),
+            &mut out,
+            &policies,
+            &mut metrics,
+        )
+        .unwrap();
+        out.finish().unwrap();
+
+        let merged = Shard::open(&dir.join("merged")).unwrap();
+        let counts = merged
+            .values::<u32>("counts")
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(counts.unwrap(), vec![(1, 5), (2, 1), (3, 2)]);
+        assert_eq!(merged.get_value::<u32>("rest", 1).unwrap(), Some(2));
+        assert_eq!(merged.get_value::<u32>("new", 5).unwrap(), Some(5));
+        assert_eq!(
+            metrics.tables["counts"],
+            TableMetrics {
+                merged: 3,
+                shared: 2,
+                conflicts: 1
+            }
+        );
+        assert!(!metrics.tables.contains_key("rest"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,678 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{ensure, Context};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::shard::{decode_record, encode_record};
+use crate::{Corruption, Header, MergeMetrics, Migrations, ShardPolicies, StorageMetrics};
+
+/// Holds the header the database was created with, apart from the tables of records.
+const META: &str = "silkworm_meta";
+
+/// The schema name another database is attached under while it is ingested.
+const OTHER: &str = "silkworm_other";
+
+/// Rows `values` reads per query.
+const PAGE: usize = 1024;
+
+type Extract = Box<dyn Fn(Header, &[u8]) -> Result<Value, anyhow::Error>>;
+
+/// Indexed columns computed from each value as it is written, for querying tables with SQL.
+#[derive(Default)]
+pub struct SqliteColumns {
+    tables: HashMap<&'static str, Vec<(&'static str, Extract)>>,
+}
+
+impl SqliteColumns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an indexed `column` to `table`, filled in from each of its values by `extract`.
+    pub fn column<V: DeserializeOwned, T: Into<Value>>(
+        mut self,
+        table: &'static str,
+        column: &'static str,
+        extract: impl Fn(&V) -> T + 'static,
+    ) -> Self {
+        let extract = move |header: Header, payload: &[u8]| {
+            Ok(extract(&decode_record(header, payload)?).into())
+        };
+        self.tables
+            .entry(table)
+            .or_default()
+            .push((column, Box::new(extract)));
+        self
+    }
+
+    fn of(&self, table: &str) -> &[(&'static str, Extract)] {
+        self.tables.get(table).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// A database kept in SQLite, one SQL table per table of records.
+///
+/// Each table is keyed by content id, stored as the `id` integer column bit for bit, with the
+/// encoded and compressed value in `payload` and any columns declared with [`SqliteColumns`]
+/// next to it. The file can be queried by anything that reads SQLite, so it can serve as the
+/// final result database as it is.
+pub struct SqliteStore {
+    conn: Connection,
+    loc: PathBuf,
+    header: Header,
+    columns: Rc<SqliteColumns>,
+    migrations: Option<(Rc<Migrations>, u32)>,
+    storage: Cell<StorageMetrics>,
+}
+
+impl SqliteStore {
+    /// Creates an empty database at `loc`, which must not exist yet.
+    pub fn create(loc: impl Into<PathBuf>, header: Header) -> Result<Self, anyhow::Error> {
+        let loc = loc.into();
+        ensure!(!loc.exists(), "{} already exists", loc.display());
+        let conn = Connection::open(&loc).with_context(|| format!("creating {}", loc.display()))?;
+
+        conn.execute(
+            &format!("CREATE TABLE {META} (key TEXT PRIMARY KEY, value BLOB NOT NULL)"),
+            [],
+        )?;
+        conn.execute(
+            &format!("INSERT INTO {META} (key, value) VALUES ('header', ?1)"),
+            [&header.to_bytes()[..]],
+        )?;
+        Ok(Self::with_conn(conn, loc, header))
+    }
+
+    pub fn open(loc: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let loc = loc.into();
+        ensure!(loc.exists(), "{} does not exist", loc.display());
+        let conn = Connection::open(&loc).with_context(|| format!("opening {}", loc.display()))?;
+
+        let header: Vec<u8> = conn
+            .query_row(
+                &format!("SELECT value FROM {META} WHERE key = 'header'"),
+                [],
+                |row| row.get(0),
+            )
+            .context("not a sqlite store")?;
+        let (header, _) = Header::parse(&header)?;
+        Ok(Self::with_conn(conn, loc, header))
+    }
+
+    fn with_conn(conn: Connection, loc: PathBuf, header: Header) -> Self {
+        SqliteStore {
+            conn,
+            loc,
+            header,
+            columns: Rc::default(),
+            migrations: None,
+            storage: Cell::default(),
+        }
+    }
+
+    /// Runs SQLite's own integrity check over every page of the database.
+    pub fn verify(&self) -> Result<(), anyhow::Error> {
+        let problems = self
+            .conn
+            .prepare("PRAGMA integrity_check")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        match &problems[..] {
+            [ok] if ok == "ok" => Ok(()),
+            _ => Err(Corruption::Malformed(problems.join("; ")).into()),
+        }
+    }
+
+    /// Fills in and indexes `columns` for the tables written from now on.
+    pub fn with_columns(self, columns: Rc<SqliteColumns>) -> Self {
+        SqliteStore { columns, ..self }
+    }
+
+    /// Upgrades values to schema version `schema` as they are decoded, each table as its own kind.
+    pub fn migrated(self, migrations: Rc<Migrations>, schema: u32) -> Self {
+        SqliteStore {
+            migrations: Some((migrations, schema)),
+            ..self
+        }
+    }
+
+    pub fn loc(&self) -> &Path {
+        &self.loc
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// The open connection, for queries over the tables.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Bytes of values put so far, before and after compression. Records `ingest` copies as
+    /// they are aren't counted again.
+    pub fn storage_metrics(&self) -> StorageMetrics {
+        self.storage.get()
+    }
+
+    fn decode<V: DeserializeOwned>(&self, table: &str, value: &[u8]) -> Result<V, anyhow::Error> {
+        match &self.migrations {
+            Some((migrations, schema)) => {
+                let value = self.header.compression.decompress(value)?;
+                migrations.decode(table, self.header, *schema, &value)
+            }
+            None => decode_record(self.header, value),
+        }
+    }
+
+    pub fn tables(&self) -> Result<Vec<String>, anyhow::Error> {
+        let mut tables = self.conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name != ?1 ORDER BY name",
+        )?;
+        let names = tables
+            .query_map([META], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(names)
+    }
+
+    fn has_table(&self, name: &str) -> Result<bool, anyhow::Error> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                [name],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some() && name != META)
+    }
+
+    pub fn len(&self, name: &str) -> Result<usize, anyhow::Error> {
+        if !self.has_table(name)? {
+            return Ok(0);
+        }
+        let len: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM {}", quote(name)),
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(len as usize)
+    }
+
+    pub fn is_empty(&self, name: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.len(name)? == 0)
+    }
+
+    /// Creates a table and the indexes of its declared columns, if it doesn't exist yet.
+    fn create_table(&self, name: &str) -> Result<(), anyhow::Error> {
+        ensure!(name != META, "{META} is reserved");
+        let columns = self.columns.of(name);
+        let mut definition = String::from("id INTEGER PRIMARY KEY, payload BLOB NOT NULL");
+        for (column, _) in columns {
+            definition.push_str(&format!(", {}", quote(column)));
+        }
+        self.conn.execute(
+            &format!("CREATE TABLE IF NOT EXISTS {} ({definition})", quote(name)),
+            [],
+        )?;
+
+        for (column, _) in columns {
+            self.conn.execute(
+                &format!(
+                    "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+                    quote(&format!("{name}_{column}")),
+                    quote(name),
+                    quote(column)
+                ),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Puts an encoded and compressed value, filling in its table's declared columns.
+    fn insert(&self, name: &str, key: u64, payload: &[u8]) -> Result<(), anyhow::Error> {
+        let columns = self.columns.of(name);
+        let mut names = String::from("id, payload");
+        let mut values = vec![Value::Integer(key as i64), Value::Blob(payload.to_vec())];
+        for (column, extract) in columns {
+            names.push_str(&format!(", {}", quote(column)));
+            values.push(
+                extract(self.header, payload)
+                    .with_context(|| format!("computing {name}.{column}"))?,
+            );
+        }
+        let placeholders = (1..=values.len())
+            .map(|at| format!("?{at}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        self.conn
+            .prepare_cached(&format!(
+                "INSERT OR REPLACE INTO {} ({names}) VALUES ({placeholders})",
+                quote(name)
+            ))?
+            .execute(params_from_iter(values))?;
+
+        let mut storage = self.storage.get();
+        storage += StorageMetrics {
+            raw: self
+                .header
+                .compression
+                .decompressed_len(payload)
+                .unwrap_or(payload.len()) as u64,
+            stored: payload.len() as u64,
+        };
+        self.storage.set(storage);
+        Ok(())
+    }
+
+    fn payload(&self, name: &str, key: u64) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        if !self.has_table(name)? {
+            return Ok(None);
+        }
+        let payload = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT payload FROM {} WHERE id = ?1",
+                quote(name)
+            ))?
+            .query_row([key as i64], |row| row.get(0))
+            .optional()?;
+        Ok(payload)
+    }
+
+    /// Puts one value, replacing whatever the key held.
+    pub fn put<V: Serialize>(&self, name: &str, key: u64, value: &V) -> Result<(), anyhow::Error> {
+        self.write_table(name, [(&key, value)])
+    }
+
+    /// Puts many values into a table in one transaction.
+    pub fn write_table<'a, V: Serialize + 'a>(
+        &self,
+        name: &str,
+        records: impl IntoIterator<Item = (&'a u64, &'a V)>,
+    ) -> Result<(), anyhow::Error> {
+        let txn = self.conn.unchecked_transaction()?;
+        self.create_table(name)?;
+        for (key, value) in records {
+            self.insert(name, *key, &encode_record(self.header, value)?)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_value<V: DeserializeOwned>(
+        &self,
+        name: &str,
+        key: u64,
+    ) -> Result<Option<V>, anyhow::Error> {
+        self.payload(name, key)?
+            .map(|payload| self.decode(name, &payload))
+            .transpose()
+    }
+
+    /// Every value of a table in key order, empty if the database has no such table. Rows are
+    /// read a page at a time and decoded as they are reached.
+    ///
+    /// Keys are ordered as unsigned, unlike the `id` column they are stored in.
+    pub fn values<'a, V: DeserializeOwned>(
+        &'a self,
+        name: &'a str,
+    ) -> Result<impl Iterator<Item = Result<(u64, V), anyhow::Error>> + 'a, anyhow::Error> {
+        let mut done = !self.has_table(name)?;
+        let mut after = None;
+        let mut page = Vec::<(i64, Vec<u8>)>::new().into_iter();
+        Ok(std::iter::from_fn(move || loop {
+            if let Some((key, payload)) = page.next() {
+                return Some(self.decode(name, &payload).map(|value| (key as u64, value)));
+            }
+            if done {
+                return None;
+            }
+            match self.page(name, after) {
+                Ok(rows) => {
+                    done = rows.len() < PAGE;
+                    after = rows.last().map(|(key, _)| *key);
+                    page = rows.into_iter();
+                }
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e));
+                }
+            }
+        }))
+    }
+
+    /// The rows after the key `after` in unsigned key order: non-negative ids, then negative ones.
+    fn page(&self, name: &str, after: Option<i64>) -> Result<Vec<(i64, Vec<u8>)>, anyhow::Error> {
+        let mut rows = self.conn.prepare_cached(&format!(
+            "SELECT id, payload FROM {} WHERE ?1 IS NULL OR (id < 0, id) > (?1 < 0, ?1) \
+             ORDER BY id < 0, id LIMIT ?2",
+            quote(name)
+        ))?;
+        let rows = rows
+            .query_map(params![after, PAGE as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+
+    /// Deletes one record, returning whether it existed.
+    pub fn delete(&self, name: &str, key: u64) -> Result<bool, anyhow::Error> {
+        if !self.has_table(name)? {
+            return Ok(false);
+        }
+        let deleted = self.conn.execute(
+            &format!("DELETE FROM {} WHERE id = ?1", quote(name)),
+            params![key as i64],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Puts every record of `other` into this database in one transaction, recording what
+    /// policies did in `metrics`.
+    ///
+    /// `other` is attached to this database's connection, so records are copied by SQL without
+    /// passing through here, along with the declared columns `other` has too. Only keys both
+    /// databases hold are read, to be resolved as `merge_shards` resolves them with this one as
+    /// the first, and records of tables whose declared columns `other` lacks, to compute them.
+    /// As records aren't decoded otherwise, both must share a header.
+    pub fn ingest(
+        &self,
+        other: &SqliteStore,
+        policies: &ShardPolicies,
+        metrics: &mut MergeMetrics,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            self.header == other.header,
+            "can't ingest a database written as {:?} into {:?}",
+            other.header,
+            self.header
+        );
+        for name in policies.tables() {
+            metrics.tables.entry(name).or_default();
+        }
+
+        // attaching can't happen inside a transaction
+        let path = other.loc.to_str().context("attached paths must be UTF-8")?;
+        self.conn
+            .execute(&format!("ATTACH DATABASE ?1 AS {OTHER}"), [path])
+            .with_context(|| format!("attaching {path}"))?;
+        let ingested = self.ingest_attached(other, policies, metrics);
+        self.conn.execute(&format!("DETACH DATABASE {OTHER}"), [])?;
+        ingested
+    }
+
+    fn ingest_attached(
+        &self,
+        other: &SqliteStore,
+        policies: &ShardPolicies,
+        metrics: &mut MergeMetrics,
+    ) -> Result<(), anyhow::Error> {
+        let txn = self.conn.unchecked_transaction()?;
+        for name in other.tables()? {
+            self.create_table(&name)?;
+            let (into, from) = (
+                format!("main.{}", quote(&name)),
+                format!("{OTHER}.{}", quote(&name)),
+            );
+
+            if let Some((name, resolve)) = policies.resolver(&name) {
+                let table = metrics.tables.entry(name).or_default();
+                table.merged += other.len(name)?;
+                let shared: Vec<(i64, Vec<u8>, Vec<u8>)> = self
+                    .conn
+                    .prepare(&format!(
+                        "SELECT theirs.id, ours.payload, theirs.payload \
+                         FROM {from} AS theirs JOIN {into} AS ours ON ours.id = theirs.id"
+                    ))?
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<_, _>>()?;
+                for (key, value, other) in shared {
+                    table.shared += 1;
+                    if value != other {
+                        table.conflicts += 1;
+                    }
+                    self.insert(name, key as u64, &resolve(self.header, &value, &other)?)?;
+                }
+            }
+
+            let declared: Vec<&str> = self
+                .columns
+                .of(&name)
+                .iter()
+                .map(|(column, _)| *column)
+                .collect();
+            if declared
+                .iter()
+                .all(|column| other.has_column(&name, column))
+            {
+                let columns = ["id", "payload"]
+                    .into_iter()
+                    .chain(declared)
+                    .map(quote)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                // keys resolved above are ignored, any other key is replaced like `put` replaces it
+                let conflict = match policies.resolver(&name) {
+                    Some(_) => "IGNORE",
+                    None => "REPLACE",
+                };
+                self.conn.execute(
+                    &format!(
+                        "INSERT OR {conflict} INTO {into} ({columns}) SELECT {columns} FROM {from}"
+                    ),
+                    [],
+                )?;
+            } else {
+                let resolved = policies.resolver(&name).is_some();
+                let mut rows = other
+                    .conn
+                    .prepare(&format!("SELECT id, payload FROM {}", quote(&name)))?;
+                let mut rows = rows.query([])?;
+                while let Some(row) = rows.next()? {
+                    let key = row.get::<_, i64>(0)? as u64;
+                    if resolved && self.payload(&name, key)?.is_some() {
+                        continue;
+                    }
+                    self.insert(&name, key, &row.get::<_, Vec<u8>>(1)?)?;
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn has_column(&self, table: &str, column: &str) -> bool {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2",
+                [table, column],
+                |_| Ok(()),
+            )
+            .is_ok()
+    }
+}
+
+/// Quotes a table or column name for SQL.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, Compression, Policy, TableMetrics};
+    use std::fs;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("silkworm-sqlite-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn it_puts_and_queries_tables() {
+        let dir = dir("query");
+        let header = Header::new(Codec::Json, 1).with_compression(Compression::Lz4);
+        let columns = SqliteColumns::new()
+            .column("words", "word", |word: &String| word.clone())
+            .column("words", "length", |word: &String| word.len() as i64);
+        let store = SqliteStore::create(dir.join("db"), header)
+            .unwrap()
+            .with_columns(Rc::new(columns));
+        let words = HashMap::from([(u64::MAX, "silk".to_string()), (2, "worm".to_string())]);
+        store.write_table("words", &words).unwrap();
+        store.put("words", 3, &"cocoon".to_string()).unwrap();
+        store.put("my \"table\"", 1, &1u32).unwrap();
+        drop(store);
+
+        let store = SqliteStore::open(dir.join("db")).unwrap();
+        assert_eq!(store.header(), header);
+        assert_eq!(store.tables().unwrap(), vec!["my \"table\"", "words"]);
+        assert_eq!(store.len("words").unwrap(), 3);
+        assert!(store.is_empty("missing").unwrap());
+        assert_eq!(
+            store.get_value::<String>("words", u64::MAX).unwrap(),
+            Some("silk".to_string())
+        );
+        assert_eq!(
+            store
+                .values::<String>("words")
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![
+                (2, "worm".to_string()),
+                (3, "cocoon".to_string()),
+                (u64::MAX, "silk".to_string())
+            ]
+        );
+
+        let long: Vec<String> = store
+            .connection()
+            .prepare("SELECT word FROM words WHERE length > 4 ORDER BY word")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(long, vec!["cocoon"]);
+
+        assert!(store.delete("words", 3).unwrap());
+        assert!(!store.delete("words", 3).unwrap() && !store.delete("missing", 3).unwrap());
+        assert_eq!(store.get_value::<String>("words", 3).unwrap(), None);
+        assert!(SqliteStore::create(dir.join("db"), header).is_err());
+        assert!(SqliteStore::open(dir.join("missing")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_pages_through_values_in_unsigned_key_order() {
+        let dir = dir("pages");
+        let store = SqliteStore::create(dir.join("db"), Header::new(Codec::Bincode, 1)).unwrap();
+        let keys: Vec<u64> = (0..PAGE as u64 * 2)
+            .flat_map(|n| [n, u64::MAX - n])
+            .collect();
+        store
+            .write_table("keys", keys.iter().map(|key| (key, key)))
+            .unwrap();
+
+        let values: Vec<(u64, u64)> = store
+            .values("keys")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        assert_eq!(values.len(), keys.len());
+        assert!(values.iter().map(|(key, _)| *key).eq(sorted));
+        assert!(values.iter().all(|(key, value)| key == value));
+        assert_eq!(store.values::<u64>("missing").unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_ingests_one_database_into_another() {
+        let dir = dir("ingest");
+        let header = Header::new(Codec::Bincode, 1);
+        let columns = Rc::new(SqliteColumns::new().column("counts", "count", |n: &u32| *n));
+        let create = |name: &str, tables: &[(&str, HashMap<u64, u32>)]| {
+            let store = SqliteStore::create(dir.join(name), header)
+                .unwrap()
+                .with_columns(columns.clone());
+            for (name, records) in tables {
+                store.write_table(name, records).unwrap();
+            }
+            store
+        };
+        let first = create(
+            "first",
+            &[
+                ("counts", HashMap::from([(1, 2), (3, 1)])),
+                ("rest", HashMap::from([(1, 1)])),
+            ],
+        );
+        let second = create(
+            "second",
+            &[
+                ("counts", HashMap::from([(1, 3), (2, 1), (3, 1)])),
+                ("rest", HashMap::from([(1, 2)])),
+                ("new", HashMap::from([(5, 5)])),
+            ],
+        );
+
+        let policies = ShardPolicies::new().table("counts", Policy::<u32>::sum());
+        let mut metrics = MergeMetrics::default();
+        first.ingest(&second, &policies, &mut metrics).unwrap();
+
+        assert_eq!(
+            first
+                .values::<u32>("counts")
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![(1, 5), (2, 1), (3, 2)]
+        );
+        let total: i64 = first
+            .connection()
+            .query_row("SELECT SUM(count) FROM counts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(total, 8);
+        assert_eq!(first.get_value::<u32>("rest", 1).unwrap(), Some(2));
+        assert_eq!(first.get_value::<u32>("new", 5).unwrap(), Some(5));
+        assert_eq!(
+            metrics.tables["counts"],
+            TableMetrics {
+                merged: 3,
+                shared: 2,
+                conflicts: 1
+            }
+        );
+        assert!(!metrics.tables.contains_key("rest"));
+
+        // columns the other database lacks are computed as its records are put
+        let plain = SqliteStore::create(dir.join("plain"), header).unwrap();
+        plain.put("counts", 7, &7u32).unwrap();
+        first.ingest(&plain, &policies, &mut metrics).unwrap();
+        let total: i64 = first
+            .connection()
+            .query_row("SELECT SUM(count) FROM counts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(total, 15);
+
+        let json = SqliteStore::create(dir.join("json"), Header::new(Codec::Json, 1)).unwrap();
+        assert!(json.ingest(&second, &policies, &mut metrics).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,801 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{
+    DatabaseStore, Header, Migrations, S3Store, Shard, ShardWriter, SqliteColumns, SqliteStore,
+};
+
+type Naming = Box<dyn Fn(&str, &str, &str) -> String>;
+
+/// Bytes written before and after compression.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageMetrics {
+    pub raw: u64,
+    pub stored: u64,
+}
+
+impl StorageMetrics {
+    /// How many times smaller compression made what was written.
+    pub fn ratio(&self) -> f64 {
+        if self.stored == 0 {
+            1.0
+        } else {
+            self.raw as f64 / self.stored as f64
+        }
+    }
+}
+
+impl AddAssign for StorageMetrics {
+    fn add_assign(&mut self, other: Self) {
+        self.raw += other.raw;
+        self.stored += other.stored;
+    }
+}
+
+/// Persists databases, queues and their sidecar files under one directory.
+///
+/// Writes go to a temporary file in the same directory that is synced and then renamed
+/// over the target, so readers only ever see a whole file. Every file starts with a [`Header`],
+/// and files from older schema versions are upgraded by the store's [`Migrations`] on read.
+///
+/// With `with_s3`, files are kept as objects in a bucket instead, keyed by their path under the
+/// root, and the root only caches the shards and databases opened from it.
+pub struct FsStore {
+    root: PathBuf,
+    naming: Naming,
+    header: Header,
+    migrations: Rc<Migrations>,
+    columns: Rc<SqliteColumns>,
+    storage: Cell<StorageMetrics>,
+    objects: Option<S3Store>,
+    cache_limit: Option<u64>,
+    /// Shards and databases this store cached and their sizes, least recently used first.
+    cached: RefCell<VecDeque<(PathBuf, u64)>>,
+}
+
+impl FsStore {
+    /// Creates `root` if needed. Files are named `<kind><worker><unique>` until `with_naming`.
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let root = root.into();
+        fs::create_dir_all(&root)
+            .with_context(|| format!("creating store directory {}", root.display()))?;
+
+        Ok(FsStore {
+            root,
+            naming: Box::new(|kind, worker, unique| format!("{kind}{worker}{unique}")),
+            header: Header::default(),
+            migrations: Rc::default(),
+            columns: Rc::default(),
+            storage: Cell::default(),
+            objects: None,
+            cache_limit: None,
+            cached: RefCell::default(),
+        })
+    }
+
+    /// Sets the codec, compression and schema version written from now on.
+    pub fn with_header(self, header: Header) -> Self {
+        FsStore { header, ..self }
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    pub fn with_migrations(self, migrations: Migrations) -> Self {
+        FsStore {
+            migrations: Rc::new(migrations),
+            ..self
+        }
+    }
+
+    /// Sets the indexed columns of the SQLite databases written from now on.
+    pub fn with_columns(self, columns: SqliteColumns) -> Self {
+        FsStore {
+            columns: Rc::new(columns),
+            ..self
+        }
+    }
+
+    /// Keeps files in a bucket, so workers on hosts that don't share a filesystem share a store.
+    ///
+    /// Shards and databases are still written locally first and uploaded once whole, and are
+    /// downloaded into the root the first time they are opened. Every file is written once under
+    /// a unique name, except for fixed names like pointers, which are never cached.
+    pub fn with_s3(self, objects: S3Store) -> Self {
+        FsStore {
+            objects: Some(objects),
+            ..self
+        }
+    }
+
+    /// Caps the bytes of shards and databases this store caches in the root of a store kept in a
+    /// bucket, evicting the least recently used ones beyond it. Files other stores put in a
+    /// shared root are left alone.
+    ///
+    /// Removing a file only drops the copy cached by this store, so without a cap the copies
+    /// other hosts cached of files merged away stay until someone clears the root.
+    pub fn with_cache_limit(self, bytes: u64) -> Self {
+        FsStore {
+            cache_limit: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Names files from their kind (like `database` or `queue`), worker name and unique string.
+    pub fn with_naming(self, naming: impl Fn(&str, &str, &str) -> String + 'static) -> Self {
+        FsStore {
+            naming: Box::new(naming),
+            ..self
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn location(&self, kind: &str, worker: &str, unique: &str) -> PathBuf {
+        self.path((self.naming)(kind, worker, unique))
+    }
+
+    /// A file directly under the root, for fixed names like a pointer to the latest database.
+    pub fn path(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// A file kept next to `loc`, like the provenance of a database.
+    pub fn sidecar(&self, loc: &Path, suffix: &str) -> PathBuf {
+        let mut name = loc.file_name().unwrap_or_default().to_owned();
+        name.push(".");
+        name.push(suffix);
+        loc.with_file_name(name)
+    }
+
+    /// Encodes a value with this store's header, as files are written and queue messages sent.
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        let payload = self.header.codec.encode(value)?;
+        let bytes = self.header.frame(&payload)?;
+        self.record(StorageMetrics {
+            raw: (self.header.framing_len() + payload.len()) as u64,
+            stored: bytes.len() as u64,
+        });
+        Ok(bytes)
+    }
+
+    /// Everything this store has encoded so far, files, shards and messages alike.
+    pub fn storage_metrics(&self) -> StorageMetrics {
+        self.storage.get()
+    }
+
+    fn record(&self, metrics: StorageMetrics) {
+        let mut storage = self.storage.get();
+        storage += metrics;
+        self.storage.set(storage);
+    }
+
+    /// Decodes what `encode` produced with any codec, migrating it as a `kind` value if it was
+    /// written under an older schema version.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        bytes: &[u8],
+    ) -> Result<T, anyhow::Error> {
+        let (header, payload) = Header::unframe(bytes)?;
+        self.migrations
+            .decode(kind, header, self.header.schema, &payload)
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&self, loc: &Path, value: &T) -> Result<(), anyhow::Error> {
+        let bytes = self.encode(value)?;
+        self.write_bytes(loc, &bytes)
+            .with_context(|| format!("writing {}", loc.display()))
+    }
+
+    fn write_bytes(&self, loc: &Path, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        if let Some(objects) = &self.objects {
+            return objects.put(&self.key(loc)?, bytes);
+        }
+        let temp = temp_path(loc)?;
+        let mut file = File::create(&temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        commit(&temp, loc)
+    }
+
+    /// Writes a shard with this store's header, filled in by `fill`.
+    pub fn write_shard(
+        &self,
+        loc: &Path,
+        fill: impl FnOnce(&mut ShardWriter) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let mut shard = ShardWriter::create(loc, self.header)?;
+        fill(&mut shard)?;
+        self.record(shard.finish()?);
+        self.upload(loc)
+    }
+
+    /// Opens a shard whose tables are migrated as they are read.
+    pub fn open_shard(&self, loc: &Path) -> Result<Shard, anyhow::Error> {
+        self.download(loc)?;
+        Ok(Shard::open(loc)?.migrated(self.migrations.clone(), self.header.schema))
+    }
+
+    /// Writes a key-value database with this store's header, filled in by `fill`.
+    pub fn write_database(
+        &self,
+        loc: &Path,
+        fill: impl FnOnce(&DatabaseStore) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        self.fill_database(loc, None, |db| db, fill)
+    }
+
+    /// Writes a key-value database at `loc` by filling the one at `base` in place, so `fill`
+    /// only puts what is new, like another database ingested into it. The base is moved to `loc`
+    /// rather than copied, so it is consumed unless `fill` fails.
+    pub fn extend_database(
+        &self,
+        base: &Path,
+        loc: &Path,
+        fill: impl FnOnce(&DatabaseStore) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        self.fill_database(loc, Some(base), |db| db, fill)
+    }
+
+    /// Writes a SQLite database with this store's header and columns, filled in by `fill`.
+    pub fn write_sqlite(
+        &self,
+        loc: &Path,
+        fill: impl FnOnce(&SqliteStore) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let columns = self.columns.clone();
+        self.fill_database(loc, None, |db: SqliteStore| db.with_columns(columns), fill)
+    }
+
+    /// Writes a SQLite database at `loc` by filling the one at `base` in place, consuming the
+    /// base like `extend_database`.
+    pub fn extend_sqlite(
+        &self,
+        base: &Path,
+        loc: &Path,
+        fill: impl FnOnce(&SqliteStore) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let columns = self.columns.clone();
+        self.fill_database(
+            loc,
+            Some(base),
+            |db: SqliteStore| db.with_columns(columns),
+            fill,
+        )
+    }
+
+    /// Writes a file database through a temporary file, set up by `setup` before `fill` runs.
+    ///
+    /// A base is renamed to the temporary file instead of copied, and renamed back if `fill`
+    /// fails, so `fill` should commit all it puts in one transaction.
+    fn fill_database<D: FileDatabase>(
+        &self,
+        loc: &Path,
+        base: Option<&Path>,
+        setup: impl FnOnce(D) -> D,
+        fill: impl FnOnce(&D) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let temp = temp_path(loc)?;
+        if let Some(base) = base {
+            self.download(base)?;
+            fs::rename(base, &temp).with_context(|| format!("moving {}", base.display()))?;
+            self.forget(base);
+        }
+        let filled = match base {
+            Some(_) => D::open(&temp),
+            None => D::create(&temp, self.header),
+        }
+        .and_then(|db| {
+            let db = setup(db);
+            fill(&db)?;
+            Ok(db)
+        });
+
+        let db = match (filled, base) {
+            (Ok(db), _) => db,
+            (Err(e), Some(base)) => {
+                fs::rename(&temp, base)
+                    .with_context(|| format!("restoring {} after {e:#}", base.display()))?;
+                return Err(e);
+            }
+            (Err(e), None) => {
+                remove_file(&temp)?;
+                return Err(e);
+            }
+        };
+        self.record(db.storage_metrics());
+        drop(db);
+        commit(&temp, loc).with_context(|| format!("writing {}", loc.display()))?;
+        self.upload(loc)
+    }
+
+    /// Opens a key-value database whose tables are migrated as they are read.
+    pub fn open_database(&self, loc: &Path) -> Result<DatabaseStore, anyhow::Error> {
+        self.download(loc)?;
+        Ok(DatabaseStore::open(loc)?.migrated(self.migrations.clone(), self.header.schema))
+    }
+
+    /// Opens a SQLite database whose tables are migrated as they are read.
+    pub fn open_sqlite(&self, loc: &Path) -> Result<SqliteStore, anyhow::Error> {
+        self.download(loc)?;
+        Ok(SqliteStore::open(loc)?.migrated(self.migrations.clone(), self.header.schema))
+    }
+
+    pub fn read<T: DeserializeOwned>(&self, kind: &str, loc: &Path) -> Result<T, anyhow::Error> {
+        self.read_optional(kind, loc)?
+            .with_context(|| format!("{} does not exist", loc.display()))
+    }
+
+    pub fn read_optional<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        loc: &Path,
+    ) -> Result<Option<T>, anyhow::Error> {
+        let bytes = match &self.objects {
+            Some(objects) => match objects.get(&self.key(loc)?)? {
+                Some(bytes) => bytes,
+                None => return Ok(None),
+            },
+            None => match fs::read(loc) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e).with_context(|| format!("reading {}", loc.display())),
+            },
+        };
+
+        let value = self
+            .decode(kind, &bytes)
+            .with_context(|| format!("deserializing {}", loc.display()))?;
+        Ok(Some(value))
+    }
+
+    /// Removes a file, returning whether it existed.
+    pub fn remove(&self, loc: &Path) -> Result<bool, anyhow::Error> {
+        self.forget(loc);
+        let cached = remove_file(loc)?;
+        match &self.objects {
+            Some(objects) => objects.delete(&self.key(loc)?),
+            None => Ok(cached),
+        }
+    }
+
+    /// The object a location is kept as, its path under the root.
+    fn key(&self, loc: &Path) -> Result<String, anyhow::Error> {
+        let relative = loc
+            .strip_prefix(&self.root)
+            .with_context(|| format!("{} is outside of the store", loc.display()))?;
+        Ok(relative
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    fn upload(&self, loc: &Path) -> Result<(), anyhow::Error> {
+        let Some(objects) = &self.objects else {
+            return Ok(());
+        };
+        objects
+            .put_file(&self.key(loc)?, loc)
+            .with_context(|| format!("uploading {}", loc.display()))?;
+        self.cache(loc)
+    }
+
+    /// Downloads a shard or database into the root unless it is cached there already, so it
+    /// can be opened directly, as stored and without this store's migrations.
+    pub fn download(&self, loc: &Path) -> Result<(), anyhow::Error> {
+        let Some(objects) = &self.objects else {
+            return Ok(());
+        };
+        if loc.exists() {
+            self.touch(loc);
+            return Ok(());
+        }
+        let temp = temp_path(loc)?;
+        if !objects.get_file(&self.key(loc)?, &temp)? {
+            bail!("{} does not exist", loc.display());
+        }
+        commit(&temp, loc)?;
+        self.cache(loc)
+    }
+
+    /// Records a file this store put in its cache as the most recently used, then evicts the
+    /// least recently used ones until the cache fits its limit. Files already open stay
+    /// readable until they are closed.
+    fn cache(&self, loc: &Path) -> Result<(), anyhow::Error> {
+        let Some(limit) = self.cache_limit else {
+            return Ok(());
+        };
+        let len = fs::metadata(loc)?.len();
+        let mut cached = self.cached.borrow_mut();
+        cached.retain(|(path, _)| path != loc);
+        cached.push_back((loc.to_path_buf(), len));
+
+        let mut size: u64 = cached.iter().map(|(_, len)| len).sum();
+        while size > limit && cached.len() > 1 {
+            let Some((path, len)) = cached.pop_front() else {
+                break;
+            };
+            remove_file(&path)?;
+            size -= len;
+        }
+        Ok(())
+    }
+
+    fn touch(&self, loc: &Path) {
+        let mut cached = self.cached.borrow_mut();
+        if let Some(at) = cached.iter().position(|(path, _)| path == loc) {
+            let entry = cached.remove(at);
+            cached.extend(entry);
+        }
+    }
+
+    /// Stops tracking a cached file that was removed or moved away.
+    fn forget(&self, loc: &Path) {
+        self.cached.borrow_mut().retain(|(path, _)| path != loc);
+    }
+}
+
+fn remove_file(loc: &Path) -> Result<bool, anyhow::Error> {
+    match fs::remove_file(loc) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("removing {}", loc.display())),
+    }
+}
+
+/// A database kept in a single file, which `FsStore` writes like any other file.
+pub(crate) trait FileDatabase: Sized {
+    fn create(loc: &Path, header: Header) -> Result<Self, anyhow::Error>;
+    fn open(loc: &Path) -> Result<Self, anyhow::Error>;
+    fn storage_metrics(&self) -> StorageMetrics;
+}
+
+impl FileDatabase for DatabaseStore {
+    fn create(loc: &Path, header: Header) -> Result<Self, anyhow::Error> {
+        DatabaseStore::create(loc, header)
+    }
+
+    fn open(loc: &Path) -> Result<Self, anyhow::Error> {
+        DatabaseStore::open(loc)
+    }
+
+    fn storage_metrics(&self) -> StorageMetrics {
+        self.storage_metrics()
+    }
+}
+
+impl FileDatabase for SqliteStore {
+    fn create(loc: &Path, header: Header) -> Result<Self, anyhow::Error> {
+        SqliteStore::create(loc, header)
+    }
+
+    fn open(loc: &Path) -> Result<Self, anyhow::Error> {
+        SqliteStore::open(loc)
+    }
+
+    fn storage_metrics(&self) -> StorageMetrics {
+        self.storage_metrics()
+    }
+}
+
+/// Numbers the temporary files of one process, so threads writing the same location don't share one.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary file next to `loc`, so renaming it over `loc` is atomic. Every call names a new one.
+pub(crate) fn temp_path(loc: &Path) -> Result<PathBuf, anyhow::Error> {
+    let file_name = loc
+        .file_name()
+        .context("store locations name a file")?
+        .to_string_lossy();
+    if let Some(dir) = loc.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let unique = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    Ok(loc.with_file_name(format!(".{file_name}.{}.{unique}.tmp", std::process::id())))
+}
+
+/// Renames a synced temporary file over `loc`.
+pub(crate) fn commit(temp: &Path, loc: &Path) -> Result<(), anyhow::Error> {
+    fs::rename(temp, loc)?;
+
+    // the rename itself is only durable once the directory is synced, where that is possible
+    if let Some(dir) = loc.parent().and_then(|dir| File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, Compression, Corruption};
+    use std::collections::HashSet;
+
+    fn store(name: &str) -> FsStore {
+        let root =
+            std::env::temp_dir().join(format!("silkworm-store-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        FsStore::new(root).unwrap()
+    }
+
+    #[test]
+    fn it_reads_back_what_it_wrote() {
+        let store = store("roundtrip");
+        let loc = store.location("database", "worker", "abc");
+
+        assert_eq!(
+            store.read_optional::<Vec<u32>>("database", &loc).unwrap(),
+            None
+        );
+        store.write(&loc, &vec![1, 2, 3]).unwrap();
+        store.write(&loc, &vec![4]).unwrap();
+
+        assert_eq!(loc, store.root().join("databaseworkerabc"));
+        assert_eq!(store.read::<Vec<u32>>("database", &loc).unwrap(), vec![4]);
+        // reading must not disturb the file
+        assert_eq!(store.read::<Vec<u32>>("database", &loc).unwrap(), vec![4]);
+        assert_eq!(fs::read_dir(store.root()).unwrap().count(), 1);
+
+        assert!(store.remove(&loc).unwrap());
+        assert!(!store.remove(&loc).unwrap());
+        assert!(store.read::<Vec<u32>>("database", &loc).is_err());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn it_names_a_new_temporary_file_on_every_call() {
+        let loc = std::env::temp_dir().join("silkworm-temp").join("db");
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let loc = loc.clone();
+                std::thread::spawn(move || {
+                    (0..100)
+                        .map(|_| temp_path(&loc).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let temps: HashSet<PathBuf> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(temps.len(), 400);
+    }
+
+    #[test]
+    fn it_names_files_with_the_configured_scheme() {
+        let store = store("naming")
+            .with_naming(|kind, worker, unique| format!("{worker}/{kind}-{unique}.bin"));
+
+        let loc = store.location("queue", "w1", "xyz");
+        store.write(&loc, "queued").unwrap();
+
+        assert_eq!(loc, store.root().join("w1").join("queue-xyz.bin"));
+        assert_eq!(
+            store.sidecar(&loc, "provenance"),
+            store.root().join("w1/queue-xyz.bin.provenance")
+        );
+        assert_eq!(store.read::<String>("queue", &loc).unwrap(), "queued");
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn it_reads_any_codec_and_migrates_older_schemas() {
+        let json = store("codec").with_header(Header::new(Codec::Json, 2));
+        let loc = json.path("pairs");
+        json.write(&loc, &vec![(1u64, "a")]).unwrap();
+
+        let bytes = fs::read(&loc).unwrap();
+        // the payload is followed by its checksum
+        assert!(bytes[..bytes.len() - 4].ends_with(br#"[[1,"a"]]"#));
+        let cbor = FsStore::new(json.root())
+            .unwrap()
+            .with_header(Header::new(Codec::Cbor, 2));
+        assert_eq!(
+            cbor.read::<Vec<(u64, String)>>("pairs", &loc).unwrap(),
+            vec![(1, "a".to_string())]
+        );
+
+        let newer = FsStore::new(json.root())
+            .unwrap()
+            .with_header(Header::new(Codec::Json, 3))
+            .with_migrations(
+                Migrations::new().step(2, "pairs", |old: Vec<(u64, String)>| {
+                    old.into_iter().map(|(_, name)| name).collect::<Vec<_>>()
+                }),
+            );
+        assert_eq!(newer.read::<Vec<String>>("pairs", &loc).unwrap(), vec!["a"]);
+
+        let older = FsStore::new(json.root())
+            .unwrap()
+            .with_header(Header::new(Codec::Json, 1));
+        assert!(older.read::<Vec<(u64, String)>>("pairs", &loc).is_err());
+        fs::remove_dir_all(json.root()).unwrap();
+    }
+
+    #[test]
+    fn it_detects_damaged_files() {
+        let store = store("damaged");
+        let loc = store.path("queue");
+        store.write(&loc, &vec![1u64, 2, 3]).unwrap();
+        let bytes = fs::read(&loc).unwrap();
+
+        let mut flipped = bytes.clone();
+        flipped[Header::LEN + 2] ^= 1;
+        fs::write(&loc, &flipped).unwrap();
+        let e = store.read::<Vec<u64>>("queue", &loc).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Corruption>(),
+            Some(Corruption::ChecksumMismatch { .. })
+        ));
+
+        fs::write(&loc, &bytes[..6]).unwrap();
+        let e = store.read::<Vec<u64>>("queue", &loc).unwrap_err();
+        assert_eq!(e.downcast_ref::<Corruption>(), Some(&Corruption::Truncated));
+
+        // files written before checksums existed still read
+        let unchecked = Header {
+            checksum: false,
+            ..Header::default()
+        };
+        fs::write(&loc, unchecked.encode(&vec![4u64]).unwrap()).unwrap();
+        assert_eq!(store.read::<Vec<u64>>("queue", &loc).unwrap(), vec![4]);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn it_extends_a_database_in_place() {
+        let store = store("database");
+        let (base, loc) = (store.path("base"), store.path("extended"));
+        store
+            .write_database(&base, |db| db.put("counts", 1, &1u32))
+            .unwrap();
+        store
+            .extend_database(&base, &loc, |db| db.put("counts", 2, &2u32))
+            .unwrap();
+
+        assert!(!base.exists());
+        let counts = |loc: &Path| -> Vec<(u64, u32)> {
+            let db = store.open_database(loc).unwrap();
+            let values: Result<_, _> = db.values("counts").unwrap().collect();
+            values.unwrap()
+        };
+        assert_eq!(counts(&loc), vec![(1, 1), (2, 2)]);
+
+        // a failed fill leaves its base where it was
+        assert!(store
+            .extend_database(&loc, &base, |_| anyhow::bail!("failed"))
+            .is_err());
+        assert!(!base.exists());
+        assert_eq!(counts(&loc), vec![(1, 1), (2, 2)]);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn it_reports_how_much_compression_saved() {
+        let paths = vec!["edge from a to b".to_string(); 200];
+        let plain = store("plain");
+        let zstd = FsStore::new(plain.root().join("zstd"))
+            .unwrap()
+            .with_header(Header::default().with_compression(Compression::Zstd));
+
+        for store in [&plain, &zstd] {
+            store.write(&store.path("paths"), &paths).unwrap();
+            assert_eq!(
+                store
+                    .read::<Vec<String>>("paths", &store.path("paths"))
+                    .unwrap(),
+                paths
+            );
+        }
+
+        assert_eq!(plain.storage_metrics().ratio(), 1.0);
+        assert_eq!(zstd.storage_metrics().raw, plain.storage_metrics().stored);
+        assert!(zstd.storage_metrics().ratio() > 10.0);
+        fs::remove_dir_all(plain.root()).unwrap();
+    }
+
+    #[test]
+    fn it_shares_files_through_s3_between_hosts() {
+        let fake = crate::object::fake::FakeS3::start();
+        let first = store("s3-first").with_s3(fake.store());
+        let second = store("s3-second").with_s3(fake.store());
+        let (queue, shard, db) = (
+            first.location("queue", "w1/", "a"),
+            first.path("shard"),
+            first.path("db"),
+        );
+
+        first.write(&queue, &vec![1u64, 2]).unwrap();
+        first
+            .write_shard(&shard, |shard| {
+                shard.write_table("counts", &std::collections::HashMap::from([(1, 2u32)]))
+            })
+            .unwrap();
+        first
+            .write_database(&db, |db| db.put("counts", 1, &1u32))
+            .unwrap();
+        // queues live only in the bucket
+        assert!(!queue.exists());
+
+        let at = |loc: &Path| second.path(loc.strip_prefix(first.root()).unwrap());
+        assert_eq!(
+            second.read::<Vec<u64>>("queue", &at(&queue)).unwrap(),
+            vec![1, 2]
+        );
+        let shard = second.open_shard(&at(&shard)).unwrap();
+        assert_eq!(shard.get_value::<u32>("counts", 1).unwrap(), Some(2));
+        second
+            .extend_database(&at(&db), &second.path("extended"), |db| {
+                db.put("counts", 2, &2u32)
+            })
+            .unwrap();
+        assert_eq!(
+            first
+                .open_database(&first.path("extended"))
+                .unwrap()
+                .values::<u32>("counts")
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![(1, 1), (2, 2)]
+        );
+
+        assert!(second.remove(&at(&queue)).unwrap());
+        assert_eq!(
+            first.read_optional::<Vec<u64>>("queue", &queue).unwrap(),
+            None
+        );
+        assert!(first.open_shard(&first.path("missing")).is_err());
+        fs::remove_dir_all(first.root()).unwrap();
+        fs::remove_dir_all(second.root()).unwrap();
+    }
+
+    #[test]
+    fn it_evicts_the_least_recently_used_files_it_cached() {
+        let fake = crate::object::fake::FakeS3::start();
+        let shard = |store: &FsStore, name: &str| {
+            let loc = store.path(name);
+            store
+                .write_shard(&loc, |shard| {
+                    shard.write_table("counts", &std::collections::HashMap::from([(1, 2u32)]))
+                })
+                .unwrap();
+            loc
+        };
+        // another worker sharing the root, and a file nobody cached
+        let other = store("s3-cache").with_s3(fake.store());
+        let (theirs, foreign) = (shard(&other, "theirs"), other.path("foreign"));
+        fs::write(&foreign, "not cached").unwrap();
+
+        let store = FsStore::new(other.root())
+            .unwrap()
+            .with_s3(fake.store())
+            .with_cache_limit(2 * fs::metadata(&theirs).unwrap().len());
+        let [first, second, third] = ["first", "second", "third"].map(|name| shard(&store, name));
+        assert!(!first.exists() && second.exists() && third.exists());
+
+        store.open_shard(&second).unwrap();
+        let opened = store.open_shard(&first).unwrap();
+        assert_eq!(opened.get_value::<u32>("counts", 1).unwrap(), Some(2));
+        assert!(first.exists() && second.exists() && !third.exists());
+        assert!(theirs.exists() && foreign.exists());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use std::convert::Infallible;
+
+use crate::{CycleSignature, DataCycle};
+
+/// A type that lives inside the registry's `Data` sum type.
+///
+/// Implemented for each payload type (usually via [`variant!`](crate::variant)) and for
+/// cycle-local enums that group several payload types, e.g. the friends of a cycle.
+pub trait Variant<Data>: Sized {
+    fn into_data(self) -> Data;
+    fn from_data(data: &Data) -> Option<Self>;
+    /// The names of the `Data` variants this type covers.
+    fn names() -> Vec<&'static str>;
+}
+
+/// Used as `Friend` by cycles that have no friends.
+impl<Data> Variant<Data> for Infallible {
+    fn into_data(self) -> Data {
+        match self {}
+    }
+
+    fn from_data(_data: &Data) -> Option<Self> {
+        None
+    }
+
+    fn names() -> Vec<&'static str> {
+        vec![]
+    }
+}
+
+/// Implements [`Variant`] for payload types that are wrapped by a single-field variant of the sum type.
+///
+/// ```ignore
+/// silkworm::variant!(Data::Node(Node), Data::Edge(Edge));
+/// ```
+#[macro_export]
+macro_rules! variant {
+    ($($data:ident :: $name:ident ( $ty:ty )),+ $(,)?) => {
+        $(
+            impl $crate::Variant<$data> for $ty {
+                fn into_data(self) -> $data {
+                    $data::$name(self)
+                }
+
+                fn from_data(data: &$data) -> Option<Self> {
+                    match data {
+                        $data::$name(inner) => Some(inner.clone()),
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+
+                fn names() -> Vec<&'static str> {
+                    vec![stringify!($name)]
+                }
+            }
+        )+
+    };
+}
+
+/// A data cycle that declares the types it reads, befriends and produces.
+///
+/// The library takes care of moving values in and out of `Data`, so a cycle only ever
+/// sees the types it asked for. Wrap it in [`Typed`] to hand it to a [`Registry`](crate::Registry).
+pub trait TypedCycle {
+    type Database;
+    type DataRoute;
+    type Data;
+    type Input: Variant<Self::Data>;
+    type Friend: Variant<Self::Data>;
+    type Output: Variant<Self::Data>;
+
+    fn stop_categorically(&self, db: &Self::Database) -> bool;
+    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Input>;
+    fn stop_data(&self, data: &Self::Input, db: &Self::Database) -> bool;
+    fn get_friends(&self, db: &Self::Database, data: &Self::Input) -> Vec<Self::Friend>;
+    fn stop_friends(&self, friends: &[Self::Friend]) -> bool;
+    fn search(&self, data: &Self::Input, friends: &[Self::Friend]) -> Vec<Self::Output>;
+    fn store(&self, db: &mut Self::Database, data: &Self::Input) -> Option<Self::DataRoute>;
+    fn save(
+        &self,
+        db: &mut Self::Database,
+        new_data: Vec<&Self::Output>,
+    ) -> Vec<Option<Self::DataRoute>>;
+    fn public(&self) -> bool;
+    /// Priority of an output, lowest first. Only matters to priority frontiers and priority-aware global queues.
+    fn priority(&self, _output: &Self::Output) -> usize {
+        0
+    }
+}
+
+/// Adapts a [`TypedCycle`] to the untyped [`DataCycle`] used by `run_worker`.
+///
+/// Data of a variant the cycle did not declare can only show up if the registry routes data to
+/// the wrong cycle. `accepts` tells `run_worker` so before the job starts.
+///
+/// # Panics
+///
+/// If any other method is handed data of a variant the cycle did not declare.
+#[derive(Default, Clone)]
+pub struct Typed<C>(pub C);
+
+impl<C: TypedCycle> Typed<C> {
+    fn declared<V: Variant<C::Data>>(data: &C::Data) -> V {
+        V::from_data(data).unwrap_or_else(|| {
+            panic!(
+                "a cycle reading {:?} was handed data of another variant",
+                C::Input::names()
+            )
+        })
+    }
+}
+
+impl<C: TypedCycle> DataCycle for Typed<C> {
+    type Database = C::Database;
+    type DataRoute = C::DataRoute;
+    type Data = C::Data;
+
+    fn stop_categorically(&self, db: &Self::Database) -> bool {
+        self.0.stop_categorically(db)
+    }
+
+    fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<Self::Data> {
+        self.0.get_data(db, route).map(Variant::into_data)
+    }
+
+    fn stop_data(&self, data: &Self::Data, db: &Self::Database) -> bool {
+        self.0.stop_data(&Self::declared(data), db)
+    }
+
+    fn get_friends(&self, db: &Self::Database, route: &Self::DataRoute) -> Vec<Self::Data> {
+        match self.0.get_data(db, route) {
+            Some(input) => self
+                .0
+                .get_friends(db, &input)
+                .into_iter()
+                .map(Variant::into_data)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn stop_friends(&self, friends: &[Self::Data]) -> bool {
+        let friends: Vec<C::Friend> = friends.iter().map(Self::declared).collect();
+        self.0.stop_friends(&friends)
+    }
+
+    fn search(&self, data: &Self::Data, friends: &[Self::Data]) -> Vec<Self::Data> {
+        let friends: Vec<C::Friend> = friends.iter().map(Self::declared).collect();
+
+        self.0
+            .search(&Self::declared(data), &friends)
+            .into_iter()
+            .map(Variant::into_data)
+            .collect()
+    }
+
+    fn store(&self, db: &mut Self::Database, data: &Self::Data) -> Option<Self::DataRoute> {
+        self.0.store(db, &Self::declared(data))
+    }
+
+    fn save(
+        &self,
+        db: &mut Self::Database,
+        new_data: Vec<&Self::Data>,
+    ) -> Vec<Option<Self::DataRoute>> {
+        let outputs: Vec<C::Output> = new_data.into_iter().map(Self::declared).collect();
+        self.0.save(db, outputs.iter().collect())
+    }
+
+    fn public(&self) -> bool {
+        self.0.public()
+    }
+
+    fn priority(&self, data: &Self::Data) -> usize {
+        C::Output::from_data(data)
+            .map(|output| self.0.priority(&output))
+            .unwrap_or_default()
+    }
+
+    fn accepts(&self, data: &Self::Data) -> bool {
+        C::Input::from_data(data).is_some()
+    }
+
+    fn signature(&self) -> CycleSignature {
+        CycleSignature {
+            input: C::Input::names().first().copied().unwrap_or_default(),
+            friends: C::Friend::names(),
+            outputs: C::Output::names(),
+            public: self.0.public(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Data {
+        Number(u32),
+        Word(String),
+    }
+
+    crate::variant!(Data::Number(u32), Data::Word(String));
+
+    struct Doubler;
+
+    impl TypedCycle for Doubler {
+        type Database = Vec<u32>;
+        type DataRoute = usize;
+        type Data = Data;
+        type Input = u32;
+        type Friend = Infallible;
+        type Output = u32;
+
+        fn stop_categorically(&self, _db: &Self::Database) -> bool {
+            false
+        }
+
+        fn get_data(&self, db: &Self::Database, route: &Self::DataRoute) -> Option<u32> {
+            db.get(*route).copied()
+        }
+
+        fn stop_data(&self, _data: &u32, _db: &Self::Database) -> bool {
+            false
+        }
+
+        fn get_friends(&self, _db: &Self::Database, _data: &u32) -> Vec<Infallible> {
+            vec![]
+        }
+
+        fn stop_friends(&self, _friends: &[Infallible]) -> bool {
+            false
+        }
+
+        fn search(&self, data: &u32, _friends: &[Infallible]) -> Vec<u32> {
+            vec![data * 2]
+        }
+
+        fn store(&self, db: &mut Self::Database, data: &u32) -> Option<usize> {
+            db.push(*data);
+            Some(db.len() - 1)
+        }
+
+        fn save(&self, db: &mut Self::Database, new_data: Vec<&u32>) -> Vec<Option<usize>> {
+            new_data.into_iter().map(|n| self.store(db, n)).collect()
+        }
+
+        fn public(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn it_wraps_and_unwraps_through_the_sum_type() {
+        let cycle = Typed(Doubler);
+        let mut db = vec![];
+
+        let route = cycle.store(&mut db, &Data::Number(3)).unwrap();
+        let data = cycle.get_data(&db, &route).unwrap();
+
+        assert_eq!(cycle.search(&data, &[]), vec![Data::Number(6)]);
+    }
+
+    #[test]
+    fn it_refuses_undeclared_variants() {
+        let cycle = Typed(Doubler);
+        let word = Data::Word("nope".to_string());
+        let number = Data::Number(4);
+
+        assert!(cycle.accepts(&number) && !cycle.accepts(&word));
+        assert_eq!(cycle.save(&mut vec![], vec![&number]), vec![Some(0)]);
+        for refused in [
+            std::panic::catch_unwind(|| cycle.save(&mut vec![], vec![&word, &number])).is_err(),
+            std::panic::catch_unwind(|| cycle.search(&word, &[])).is_err(),
+            std::panic::catch_unwind(|| cycle.stop_data(&word, &vec![])).is_err(),
+        ] {
+            assert!(refused);
+        }
+    }
+
+    #[test]
+    fn it_declares_its_signature() {
+        assert_eq!(
+            Typed(Doubler).signature(),
+            CycleSignature {
+                input: "Number",
+                friends: vec![],
+                outputs: vec!["Number"],
+                public: false,
+            }
+        );
+    }
+}
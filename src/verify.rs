@@ -0,0 +1,222 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::shard::MAGIC as SHARD_MAGIC;
+use crate::{Header, Shard, SqliteStore};
+
+/// A stored file or record that doesn't hold what was written, as opposed to one that
+/// couldn't be read at all. Find it in an `anyhow::Error` with `downcast_ref`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// The bytes end before a header, record, index or trailer does.
+    Truncated,
+    /// Bytes don't match the checksum written alongside them.
+    ChecksumMismatch { expected: u32, found: u32 },
+    /// The bytes are whole and match their checksum, but can't be what was written.
+    Malformed(String),
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Corruption::Truncated => write!(f, "corrupt: truncated"),
+            Corruption::ChecksumMismatch { expected, found } => write!(
+                f,
+                "corrupt: checksum {found:08x} does not match {expected:08x}"
+            ),
+            Corruption::Malformed(what) => write!(f, "corrupt: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for Corruption {}
+
+/// Checksums cover the key and value of shard records, and everything before them in files.
+pub(crate) fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+pub(crate) fn check(expected: u32, found: u32) -> Result<(), Corruption> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(Corruption::ChecksumMismatch { expected, found })
+    }
+}
+
+/// How SQLite databases start.
+const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// How redb key-value stores start.
+const REDB_MAGIC: &[u8; 9] = b"redb\x1A\x0A\xA9\x0D\x0A";
+
+/// What `verify_store` found.
+#[derive(Debug, Default)]
+pub struct StoreReport {
+    /// Every file with a silkworm header and every SQLite database, damaged or not.
+    pub checked: Vec<PathBuf>,
+    pub damaged: Vec<(PathBuf, anyhow::Error)>,
+    /// Key-value stores, which redb can only check by repairing them in place.
+    pub unchecked: Vec<PathBuf>,
+}
+
+impl StoreReport {
+    pub fn is_damaged(&self, path: &Path) -> bool {
+        self.damaged.iter().any(|(damaged, _)| damaged == path)
+    }
+}
+
+/// Reads every shard, queue, sidecar file and SQLite database under `dir`, checking their
+/// checksums. Key-value stores are listed as unchecked.
+///
+/// Other files are skipped, as are the hidden temporary files of writes still in flight.
+pub fn verify_store(dir: &Path) -> Result<StoreReport, anyhow::Error> {
+    let mut report = StoreReport::default();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .with_context(|| format!("reading {}", dir.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        entries.sort();
+
+        for path in entries {
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if hidden {
+                continue;
+            }
+
+            let verified = match Kind::sniff(&path)? {
+                Kind::Shard => Shard::open(&path).and_then(|shard| Ok(shard.verify().map(drop)?)),
+                Kind::Framed => fs::read(&path)
+                    .with_context(|| format!("reading {}", path.display()))
+                    .and_then(|bytes| Header::unframe(&bytes).map(drop)),
+                Kind::Sqlite => SqliteStore::open(&path).and_then(|db| db.verify()),
+                Kind::Redb => {
+                    report.unchecked.push(path);
+                    continue;
+                }
+                Kind::Other => continue,
+            };
+            if let Err(e) = verified {
+                report.damaged.push((path.clone(), e));
+            }
+            report.checked.push(path);
+        }
+    }
+    Ok(report)
+}
+
+/// What a file holds, told from its first and last few bytes.
+enum Kind {
+    Shard,
+    /// A single value framed by a silkworm header, as queues and sidecar files are.
+    Framed,
+    Sqlite,
+    Redb,
+    Other,
+}
+
+impl Kind {
+    fn sniff(path: &Path) -> Result<Kind, anyhow::Error> {
+        let mut file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let mut start = Vec::with_capacity(SQLITE_MAGIC.len());
+        (&mut file)
+            .take(SQLITE_MAGIC.len() as u64)
+            .read_to_end(&mut start)?;
+
+        if start.starts_with(SQLITE_MAGIC) {
+            return Ok(Kind::Sqlite);
+        }
+        if start.starts_with(REDB_MAGIC) {
+            return Ok(Kind::Redb);
+        }
+        if !start.starts_with(&Header::MAGIC) {
+            return Ok(Kind::Other);
+        }
+
+        // a shard ends with its own magic, after the index
+        let mut end = [0; SHARD_MAGIC.len()];
+        if file.metadata()?.len() < (Header::LEN + end.len()) as u64 {
+            return Ok(Kind::Framed);
+        }
+        file.seek(SeekFrom::End(-(end.len() as i64)))?;
+        file.read_exact(&mut end)?;
+        Ok(if &end == SHARD_MAGIC {
+            Kind::Shard
+        } else {
+            Kind::Framed
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FsStore;
+
+    #[test]
+    fn it_reports_damaged_shards_and_files() {
+        let root = std::env::temp_dir().join(format!("silkworm-verify-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = FsStore::new(&root).unwrap();
+        let shard = |name: &str| {
+            let loc = store.path(name);
+            store
+                .write_shard(&loc, |shard| {
+                    shard.write_table("counts", &std::collections::HashMap::from([(1, 2u32)]))
+                })
+                .unwrap();
+            loc
+        };
+        let (whole, damaged) = (shard("whole"), shard("damaged"));
+        let queue = store.location("queue", "w1/", "a");
+        store.write(&queue, &vec![1u64]).unwrap();
+        let (sqlite, kv) = (store.path("sqlite"), store.path("kv"));
+        let header = Header::new(crate::Codec::Bincode, 1);
+        SqliteStore::create(&sqlite, header)
+            .and_then(|db| db.put("counts", 1, &2u32))
+            .unwrap();
+        crate::DatabaseStore::create(&kv, header).unwrap();
+        fs::write(store.path("notes.txt"), "not stored").unwrap();
+        fs::write(store.path(".damaged.1.tmp"), "SILK in flight").unwrap();
+
+        let mut bytes = fs::read(&damaged).unwrap();
+        bytes[Header::LEN + 16] ^= 1;
+        fs::write(&damaged, bytes).unwrap();
+        let report = verify_store(&root).unwrap();
+
+        assert_eq!(report.checked.len(), 4);
+        assert_eq!(report.unchecked, vec![kv]);
+        assert!(report.is_damaged(&damaged));
+        assert!(!report.is_damaged(&whole) && !report.is_damaged(&queue));
+        assert!(report.checked.contains(&sqlite) && !report.is_damaged(&sqlite));
+        assert!(matches!(
+            report.damaged[0].1.downcast_ref::<Corruption>(),
+            Some(Corruption::ChecksumMismatch { .. })
+        ));
+
+        let bytes = fs::read(&queue).unwrap();
+        fs::write(&queue, &bytes[..bytes.len() - 1]).unwrap();
+        let bytes = fs::read(&sqlite).unwrap();
+        fs::write(&sqlite, &bytes[..bytes.len() / 2]).unwrap();
+        let report = verify_store(&root).unwrap();
+        assert!(report.is_damaged(&queue) && report.is_damaged(&sqlite));
+        fs::remove_dir_all(root).unwrap();
+    }
+}